@@ -0,0 +1,59 @@
+//! Several Google accounts archived into one site.
+//!
+//! Each account lists the courses it sees. A course seen by more than one is archived once, by the account that owns
+//! it when that one is signed in, else by the first account in the configuration that sees it.
+
+use classroom1::api::Course;
+use std::collections::HashMap;
+
+/// Combines the course lists of the accounts, `lists[i]` being account `i`'s, into one list of each course with the
+/// index of the account that archives it. `me[i]` is account `i`'s user ID, when known.
+///
+/// Courses are in the order the accounts list them, the first account's first.
+pub fn assign(lists: Vec<Vec<Course>>, me: &[Option<String>]) -> Vec<(Course, usize)> {
+    let owner_of = |course: &Course| course.owner_id.as_deref().and_then(|owner| me.iter().position(|id| id.as_deref() == Some(owner)));
+    let mut assigned: Vec<(Course, usize)> = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (account, courses) in lists.into_iter().enumerate() {
+        for course in courses {
+            let Some(id) = course.id.clone() else {
+                continue;
+            };
+            match seen.get(&id) {
+                // The owner sees the most, e.g. every student's answers
+                Some(&index) if owner_of(&course) == Some(account) => assigned[index] = (course, account),
+                Some(_) => (),
+                None => {
+                    seen.insert(id, assigned.len());
+                    assigned.push((course, account));
+                }
+            }
+        }
+    }
+    assigned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn course(id: &str, owner: &str) -> Course {
+        Course { id: Some(id.to_string()), owner_id: Some(owner.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn shared_courses_go_to_their_owner() {
+        let lists = vec![
+            vec![course("1", "111"), course("2", "222"), course("3", "999")],
+            vec![course("2", "222"), course("4", "222"), course("3", "999")],
+        ];
+        let me = [Some("111".to_string()), Some("222".to_string())];
+        let assigned: Vec<(String, usize)> = assign(lists.clone(), &me).into_iter().map(|(course, account)| (course.id.unwrap(), account)).collect();
+        // Course 3's owner isn't signed in, so the first account that sees it keeps it
+        assert_eq!(assigned, [("1".to_string(), 0), ("2".to_string(), 1), ("3".to_string(), 0), ("4".to_string(), 1)]);
+
+        // Without the roster feature nobody's ID is known
+        let assigned: Vec<usize> = assign(lists, &[None, None]).into_iter().map(|(_, account)| account).collect();
+        assert_eq!(assigned, [0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,184 @@
+//! Course aliases, and merging the sections of one class into a single page.
+//!
+//! Courses are merged when they share an alias or are given the same `merge` name in the configuration. The first
+//! course of a group, in course list order, gives the merged page its ID and name.
+
+use crate::api::Api;
+use crate::fetch::CourseData;
+use classroom1::api::Course;
+use std::collections::HashMap;
+
+/// Lists every page of a course's aliases, e.g. `d:english-10` or `p:english-10`.
+pub async fn list_aliases(api: &Api, course_id: &str) -> classroom1::Result<Vec<String>> {
+    let mut aliases = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let response = api.call(|| {
+            let mut call = api.hub().courses().aliases_list(course_id).page_size(api.page_size());
+            if let Some(token) = &page_token {
+                call = call.page_token(token);
+            }
+            call.doit()
+        }).await?;
+        aliases.extend(response.aliases.unwrap_or_default().into_iter().filter_map(|alias| alias.alias));
+        page_token = response.next_page_token.filter(|token| !token.is_empty());
+        if page_token.is_none() {
+            return Ok(aliases);
+        }
+    }
+}
+
+/// Groups courses that share an alias or a configured merge name, as indexes into `courses`.
+///
+/// Groups are in course list order, and so is each group, so the first index is the course the page is named after.
+/// Courses merged with nothing are groups of one.
+pub fn merge_groups(courses: &[Course], aliases: &HashMap<String, Vec<String>>, merge: &HashMap<String, String>) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..courses.len()).collect();
+    fn root(parent: &mut [usize], index: usize) -> usize {
+        let mut index = index;
+        while parent[index] != index {
+            parent[index] = parent[parent[index]];
+            index = parent[index];
+        }
+        index
+    }
+    let mut owners: HashMap<String, usize> = HashMap::new();
+    for (index, course) in courses.iter().enumerate() {
+        let id = course.id.as_deref().unwrap_or_default();
+        // Prefixed so a configured name can't collide with an alias
+        let keys = aliases.get(id).into_iter().flatten().map(|alias| format!("alias {}", alias))
+            .chain(merge.get(id).map(|name| format!("merge {}", name)));
+        for key in keys {
+            let owner = *owners.entry(key).or_insert(index);
+            let (a, b) = (root(&mut parent, owner), root(&mut parent, index));
+            // The earlier course stays the root, so it names the group
+            parent[a.max(b)] = a.min(b);
+        }
+    }
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of: HashMap<usize, usize> = HashMap::new();
+    for index in 0..courses.len() {
+        let root = root(&mut parent, index);
+        match group_of.get(&root) {
+            Some(&group) => groups[group].push(index),
+            None => {
+                group_of.insert(root, groups.len());
+                groups.push(vec![index]);
+            }
+        }
+    }
+    groups
+}
+
+/// How a section is named on a merged page: its section, else its name.
+pub fn section_label(course: &Course) -> String {
+    course.section.clone().filter(|section| !section.is_empty())
+        .or_else(|| course.name.clone())
+        .unwrap_or_default()
+}
+
+/// Combines the data of a group's courses into one page's worth, newest posts first.
+///
+/// Items keep their `courseId`, which is what tags them with their section. Teachers and topics shared by sections
+/// are listed once; a section that failed is still named in `failed`.
+pub fn merge_data(parts: Vec<CourseData>) -> CourseData {
+    let mut merged = CourseData::default();
+    for part in parts {
+        merged.announcements.extend(part.announcements);
+        merged.coursework.extend(part.coursework);
+        merged.materials.extend(part.materials);
+        for teacher in part.teachers {
+            if teacher.user_id.is_none() || !merged.teachers.iter().any(|known| known.user_id == teacher.user_id) {
+                merged.teachers.push(teacher);
+            }
+        }
+        for topic in part.topics {
+            if !merged.topics.iter().any(|known| known.name == topic.name) {
+                merged.topics.push(topic);
+            }
+        }
+        if let Some(students) = part.students {
+            merged.students.get_or_insert_with(Vec::new).extend(students);
+        }
+        merged.answers.extend(part.answers);
+        merged.guardians.extend(part.guardians);
+        for section in part.failed {
+            if !merged.failed.contains(&section) {
+                merged.failed.push(section);
+            }
+        }
+        merged.timings.0.extend(part.timings.0);
+    }
+    merged.announcements.sort_by_key(|item| std::cmp::Reverse(item.creation_time));
+    merged.coursework.sort_by_key(|item| std::cmp::Reverse(item.creation_time));
+    merged.materials.sort_by_key(|item| std::cmp::Reverse(item.creation_time));
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use classroom1::api::{Announcement, Teacher, Topic};
+    use classroom1::chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    fn course(id: &str, section: Option<&str>) -> Course {
+        Course { id: Some(id.to_string()), name: Some(format!("Course {}", id)), section: section.map(str::to_string), ..Default::default() }
+    }
+
+    fn strings(pairs: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        pairs.iter().map(|(id, aliases)| (id.to_string(), aliases.iter().map(|alias| alias.to_string()).collect())).collect()
+    }
+
+    #[test]
+    fn shared_aliases_and_merge_names_group_courses() {
+        let courses: Vec<Course> = ["1", "2", "3", "4", "5"].into_iter().map(|id| course(id, None)).collect();
+        // 2 and 4 share an alias, 4 and 5 a configured name, so all three end up together
+        let aliases = strings(&[("2", &["d:english-10"]), ("4", &["d:english-10", "p:old"]), ("3", &["d:maths"])]);
+        let merge = HashMap::from([("5".to_string(), "english".to_string()), ("4".to_string(), "english".to_string())]);
+        assert_eq!(merge_groups(&courses, &aliases, &merge), vec![vec![0], vec![1, 3, 4], vec![2]]);
+
+        // Names and aliases live apart, even when spelled the same
+        let merge = HashMap::from([("1".to_string(), "d:maths".to_string())]);
+        assert_eq!(merge_groups(&courses, &aliases, &merge).len(), 4);
+    }
+
+    #[test]
+    fn merged_data_keeps_every_section() {
+        let at = |day| Some(Utc.with_ymd_and_hms(2024, 1, day, 9, 0, 0).unwrap());
+        let announcement = |course: &str, day| Announcement { course_id: Some(course.to_string()), creation_time: at(day), ..Default::default() };
+        let teacher = Teacher { user_id: Some("111".to_string()), ..Default::default() };
+        let topic = Topic { name: Some("Poetry".to_string()), ..Default::default() };
+        let first = CourseData {
+            announcements: vec![announcement("1", 3), announcement("1", 1)],
+            teachers: vec![teacher.clone()],
+            topics: vec![topic.clone()],
+            ..Default::default()
+        };
+        let second = CourseData {
+            announcements: vec![announcement("2", 2)],
+            teachers: vec![teacher],
+            topics: vec![topic],
+            failed: vec!["materials"],
+            ..Default::default()
+        };
+        let merged = merge_data(vec![first, second]);
+        let order: Vec<_> = merged.announcements.iter().map(|a| a.course_id.as_deref().unwrap()).collect();
+        assert_eq!(order, ["1", "2", "1"]);
+        assert_eq!((merged.teachers.len(), merged.topics.len()), (1, 1));
+        assert_eq!(merged.failed, ["materials"]);
+        assert!(merged.students.is_none());
+
+        assert_eq!(section_label(&course("1", Some("Period 2"))), "Period 2");
+        assert_eq!(section_label(&course("1", Some(""))), "Course 1");
+    }
+
+    #[tokio::test]
+    async fn aliases_are_listed() {
+        let server = MockServer::start().await;
+        server.route("/v1/courses/1/aliases", json!({"aliases": [{"courseId": "1", "alias": "d:english-10"}]}));
+        let api = server.api();
+        assert_eq!(list_aliases(&api, "1").await.unwrap(), ["d:english-10"]);
+    }
+}
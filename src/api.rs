@@ -0,0 +1,311 @@
+use crate::Hub;
+use crate::metrics::{Metrics, METRICS};
+use classroom1::hyper::header::CONTENT_LENGTH;
+use classroom1::hyper::{Body, Response};
+use rand::Rng;
+use serde::Serialize;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::{sleep, sleep_until, Instant};
+use tracing::Instrument;
+
+/// Request limits shared by every call made through an `Api`.
+#[derive(Clone, Debug, clap::Args)]
+pub struct Limits {
+    /// Classroom API requests started per second, across all courses
+    #[arg(long, default_value_t = 10.0)]
+    pub requests_per_second: f64,
+    /// Classroom API requests waiting on a response at the same time
+    #[arg(long, default_value_t = 8)]
+    pub max_in_flight: usize,
+    /// Retries for a request that hit the quota or a server error before giving up
+    #[arg(long, default_value_t = 6)]
+    pub max_retries: u32,
+    /// First backoff delay in milliseconds, doubled on every retry
+    #[arg(long, default_value_t = 500)]
+    pub backoff_ms: u64,
+    /// Longest backoff delay in milliseconds
+    #[arg(long, default_value_t = 32_000)]
+    pub max_backoff_ms: u64,
+    /// Items asked for per page when listing courses, students or submissions
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(i32).range(1..))]
+    pub page_size: i32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            requests_per_second: 10.0,
+            max_in_flight: 8,
+            max_retries: 6,
+            backoff_ms: 500,
+            max_backoff_ms: 32_000,
+            page_size: 100,
+        }
+    }
+}
+
+struct Limiter {
+    limits: Limits,
+    in_flight: Semaphore,
+    // Earliest time the next request may start
+    next_slot: Mutex<Instant>,
+}
+
+impl Limiter {
+    /// Waits for this request's turn under the requests-per-second limit.
+    async fn pace(&self) {
+        if self.limits.requests_per_second <= 0.0 {
+            return;
+        }
+        let interval = Duration::from_secs_f64(1.0 / self.limits.requests_per_second);
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + interval;
+            slot
+        };
+        sleep_until(slot).await;
+    }
+
+    /// "Full jitter" backoff: a random delay up to the exponential cap, so courses that failed together don't retry together.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let cap = self.limits.backoff_ms.saturating_mul(1 << attempt.min(20)).min(self.limits.max_backoff_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(cap / 2..=cap))
+    }
+}
+
+/// The Classroom hub behind a shared rate limiter.
+///
+/// Every request goes through `call`, which keeps to the requests-per-second and in-flight limits and retries
+/// quota and server errors with jittered exponential backoff, waiting as long as the server asks when it says.
+#[derive(Clone)]
+pub struct Api {
+    hub: Hub,
+    limiter: Arc<Limiter>,
+    // Requests in flight for one course, on top of the shared limit
+    course: Option<Arc<Semaphore>>,
+}
+
+impl Api {
+    pub fn new(hub: Hub, limits: Limits) -> Api {
+        let in_flight = Semaphore::new(limits.max_in_flight.max(1));
+        Api {
+            hub,
+            limiter: Arc::new(Limiter { limits, in_flight, next_slot: Mutex::new(Instant::now()) }),
+            course: None,
+        }
+    }
+
+    /// The same API, but with at most `max_in_flight` of this handle's requests (and its clones') in flight at once.
+    pub fn with_course_limit(&self, max_in_flight: usize) -> Api {
+        Api { course: Some(Arc::new(Semaphore::new(max_in_flight.max(1)))), ..self.clone() }
+    }
+
+    pub fn hub(&self) -> &Hub {
+        &self.hub
+    }
+
+    /// Page size for list calls, from `--page-size`.
+    pub fn page_size(&self) -> i32 {
+        self.limiter.limits.page_size
+    }
+
+    /// Runs a request built by `request`, e.g. `api.call(|| api.hub().courses().get(id).doit())`.
+    ///
+    /// The closure is called again for every retry since `doit` consumes the call builder.
+    pub async fn call<T, F, Fut>(&self, request: F) -> classroom1::Result<T>
+    where
+        T: Serialize,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = classroom1::Result<(Response<Body>, T)>>,
+    {
+        self.call_with_retries(request).instrument(tracing::info_span!("api_call")).await
+    }
+
+    async fn call_with_retries<T, F, Fut>(&self, mut request: F) -> classroom1::Result<T>
+    where
+        T: Serialize,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = classroom1::Result<(Response<Body>, T)>>,
+    {
+        let limiter = &self.limiter;
+        let mut attempt = 0;
+        loop {
+            let course_permit = match &self.course {
+                Some(course) => Some(course.acquire().await.expect("the course semaphore is never closed")),
+                None => None,
+            };
+            limiter.pace().await;
+            let start_time = std::time::Instant::now();
+            let result = {
+                let _permit = limiter.in_flight.acquire().await.expect("the limiter semaphore is never closed");
+                Metrics::add(&METRICS.api_requests, 1);
+                request().await
+            };
+            let error = match result {
+                Ok((response, value)) => {
+                    let bytes = response_bytes(&response, &value);
+                    Metrics::add(&METRICS.bytes, bytes);
+                    tracing::debug!(attempt = attempt + 1, elapsed = ?start_time.elapsed(), bytes, "Request finished");
+                    return Ok(value);
+                }
+                Err(error) => error,
+            };
+            drop(course_permit);
+            let delay = match retry_delay(&error) {
+                Some(delay) if attempt < limiter.limits.max_retries => delay.unwrap_or_else(|| limiter.backoff(attempt)),
+                _ => {
+                    Metrics::add(&METRICS.api_failures, 1);
+                    tracing::debug!(attempt = attempt + 1, error = %error_summary(&error), "Request failed");
+                    return Err(error);
+                }
+            };
+            Metrics::add(&METRICS.retries, 1);
+            tracing::warn!(attempt = attempt + 1, error = %error_summary(&error), delay = ?delay, "Request failed, retrying");
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// `Content-Length` when the server sent one (it was already read into `value`), otherwise the size of the JSON.
+fn response_bytes<T: Serialize>(response: &Response<Body>, value: &T) -> u64 {
+    response.headers().get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse().ok())
+        .unwrap_or_else(|| serde_json::to_vec(value).map_or(0, |json| json.len() as u64))
+}
+
+/// `None` if the error is not worth retrying, otherwise how long the server asked us to wait, if it did.
+fn retry_delay(error: &classroom1::Error) -> Option<Option<Duration>> {
+    match error {
+        classroom1::Error::HttpError(_) | classroom1::Error::Io(_) => Some(None),
+        classroom1::Error::Failure(response) => {
+            if !is_retryable_status(response.status().as_u16()) {
+                return None;
+            }
+            let retry_after = response.headers().get("retry-after")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+            Some(retry_after)
+        }
+        classroom1::Error::BadRequest(body) => {
+            let error = body.get("error")?;
+            let code = error.get("code").and_then(Value::as_u64).unwrap_or_default() as u16;
+            let status = error.get("status").and_then(Value::as_str).unwrap_or_default();
+            // Google reports some per-user quota errors as 403 with a rate limit reason
+            let rate_limited = error.get("errors").and_then(Value::as_array).into_iter().flatten()
+                .filter_map(|detail| detail.get("reason")?.as_str())
+                .any(|reason| reason.ends_with("RateLimitExceeded") || reason == "rateLimitExceeded");
+            if is_retryable_status(code) || status == "RESOURCE_EXHAUSTED" || status == "UNAVAILABLE" || rate_limited {
+                Some(retry_info_delay(error))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn is_retryable_status(code: u16) -> bool {
+    matches!(code, 429 | 500 | 502 | 503 | 504)
+}
+
+/// JSON errors carry `Retry-After` as a `google.rpc.RetryInfo` detail, e.g. `"retryDelay": "30s"`.
+fn retry_info_delay(error: &Value) -> Option<Duration> {
+    error.get("details")?.as_array()?.iter()
+        .filter_map(|detail| detail.get("retryDelay")?.as_str())
+        .find_map(|delay| delay.strip_suffix('s')?.parse::<f64>().ok())
+        .map(Duration::from_secs_f64)
+}
+
+fn error_summary(error: &classroom1::Error) -> String {
+    match error {
+        classroom1::Error::Failure(response) => response.status().to_string(),
+        classroom1::Error::BadRequest(body) => body["error"]["status"].as_str()
+            .map_or_else(|| body["error"]["code"].to_string(), str::to_string),
+        error => error.to_string().trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{list_courses, CourseFilter};
+    use crate::mock::{MockServer, QuotaErrors};
+    use serde_json::json;
+    use std::time::Instant as StdInstant;
+
+    fn limits() -> Limits {
+        Limits { requests_per_second: 0.0, backoff_ms: 10, max_backoff_ms: 20, ..Default::default() }
+    }
+
+    fn courses() -> Value {
+        json!({"courses": [{"id": "1", "name": "EN201"}]})
+    }
+
+    #[tokio::test]
+    async fn retries_through_quota_errors() {
+        let server = MockServer::start().await;
+        server.route("/v1/courses", courses());
+        server.fail_with(QuotaErrors { count: 2, retry_after: None, json: true });
+        let api = Api::new(server.hub(), limits());
+
+        let courses = list_courses(&api, &CourseFilter::default()).await.unwrap();
+        assert_eq!(courses.len(), 1);
+        assert_eq!(server.requests(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let server = MockServer::start().await;
+        server.route("/v1/courses", courses());
+        server.fail_with(QuotaErrors { count: 10, retry_after: None, json: false });
+        let api = Api::new(server.hub(), Limits { max_retries: 2, ..limits() });
+
+        assert!(list_courses(&api, &CourseFilter::default()).await.is_err());
+        assert_eq!(server.requests(), 3);
+    }
+
+    #[tokio::test]
+    async fn honours_retry_after() {
+        for json in [false, true] {
+            let server = MockServer::start().await;
+            server.route("/v1/courses", courses());
+            server.fail_with(QuotaErrors { count: 1, retry_after: Some(1), json });
+            let api = Api::new(server.hub(), limits());
+
+            let start = StdInstant::now();
+            list_courses(&api, &CourseFilter::default()).await.unwrap();
+            assert!(start.elapsed() >= Duration::from_secs(1), "retried after {:?}", start.elapsed());
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+        let api = Api::new(server.hub(), limits());
+
+        // Unknown routes answer 404
+        assert!(list_courses(&api, &CourseFilter::default()).await.is_err());
+        assert_eq!(server.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn paces_requests() {
+        let server = MockServer::start().await;
+        server.route("/v1/courses", courses());
+        let api = Api::new(server.hub(), Limits { requests_per_second: 20.0, ..limits() });
+
+        let start = StdInstant::now();
+        for _ in 0..5 {
+            list_courses(&api, &CourseFilter::default()).await.unwrap();
+        }
+        // The first request goes out right away, the other four wait 50ms each
+        assert!(start.elapsed() >= Duration::from_millis(200), "took {:?}", start.elapsed());
+    }
+}
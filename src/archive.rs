@@ -0,0 +1,478 @@
+//! Archiving course pages, whether by a run of `html` or by the server when Classroom says a course changed.
+//!
+//! Each section of a page is fetched by the account that sees it, the sections merged, the edits recorded and the
+//! whole redacted before it is rendered, so a page is the same whichever binary wrote it last.
+
+use crate::accounts;
+use crate::aliases::{list_aliases, merge_data, merge_groups, section_label};
+use crate::api::{Api, Limits};
+use crate::cache::{attach, check_url, CacheOptions};
+use crate::config::{AccountConfig, Config};
+use crate::daemon::Fingerprint;
+use crate::embeds::{self, EmbedOptions};
+use crate::fetch::fetch_course;
+use crate::filter::{list_courses, CourseFilter};
+use crate::history::CourseHistory;
+use crate::index::CourseStats;
+use crate::invitations::Invitations;
+use crate::profiles::Profiles;
+use crate::redact::{Redaction, Rules};
+use crate::roster::StudentPrivacy;
+use crate::schedule::Scheduler;
+use crate::scopes::{self, Feature, Features};
+use crate::theme::Theme;
+use classroom1::api::Course;
+use classroom1::{hyper, hyper_rustls, Classroom};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tera::{Context, Tera};
+use tokio::runtime::Handle;
+use tracing::Instrument;
+
+/// Why an account couldn't be signed in, its courses listed or a page written.
+#[derive(Debug)]
+pub enum Error {
+    Api(classroom1::Error),
+    Io(io::Error),
+    Template(tera::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Api(error) => write!(f, "Classroom API: {}", error),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Template(error) => {
+                // What went wrong in the template is in the errors it wraps
+                write!(f, "{}", error)?;
+                let mut source = std::error::Error::source(error);
+                while let Some(error) = source {
+                    write!(f, ": {}", error)?;
+                    source = std::error::Error::source(error);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<classroom1::Error> for Error {
+    fn from(error: classroom1::Error) -> Self {
+        Error::Api(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Io(error.into())
+    }
+}
+
+impl From<tera::Error> for Error {
+    fn from(error: tera::Error) -> Self {
+        Error::Template(error)
+    }
+}
+
+/// A signed-in account, and what sign-in allowed it.
+#[derive(Clone)]
+pub struct Account {
+    pub name: String,
+    pub api: Api,
+    pub features: Features,
+    /// The account's user ID, looked up when the roster is archived
+    pub me: Option<String>,
+    /// Names of the people in its courses, when the roster is archived
+    pub profiles: Option<Profiles>,
+}
+
+/// Signs in `account`, keeping to the `features` it was granted, and looks up who it is.
+pub async fn connect(config: &Config, account: &AccountConfig, features: &Features, limits: &Limits, cache: &CacheOptions) -> Result<Account, Error> {
+    let mut features = features.clone();
+    // Configured accounts keep their cached responses apart by name
+    let cache = if config.accounts.is_empty() {
+        cache.clone()
+    } else {
+        CacheOptions { account: account.name.clone(), ..cache.clone() }
+    };
+    let client = hyper::Client::builder().build(hyper_rustls::HttpsConnectorBuilder::new().with_native_roots()?.https_or_http().enable_http1().build());
+    let mut hub = if cache.mode.is_offline() {
+        // Nothing leaves the machine, so there is no token to get
+        Classroom::new(client, String::new())
+    } else {
+        let secret = classroom1::oauth2::read_application_secret(&config.auth.credentials)
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", config.auth.credentials.display(), e)))?;
+        let auth = classroom1::oauth2::InstalledFlowAuthenticator::builder(
+            secret,
+            classroom1::oauth2::InstalledFlowReturnMethod::HTTPRedirect,
+        )
+        .persist_tokens_to_disk(&account.tokens)
+        .build()
+        .await?;
+        let scopes = features.scopes(&config.auth.scopes);
+        match auth.token(&scopes).await {
+            // Skip what sign-in didn't allow now, rather than on a 403 halfway through
+            Ok(token) => match scopes::granted(token.token().unwrap_or_default()).await {
+                Ok(granted) => features.negotiate(&granted),
+                Err(error) => tracing::warn!(account = account.name, error = %error, "Failed to check the granted scopes"),
+            },
+            Err(e) => tracing::error!(account = account.name, error = ?e, "Failed to get a token"),
+        }
+        Classroom::new(client, auth)
+    };
+    attach(&cache, &mut hub).await?;
+    // Every course task of the account shares this one limiter, so its quota is spent at a steady rate
+    let api = Api::new(hub, limits.clone());
+    // The enrollment code is only shown on the index for courses the account teaches
+    let me = if features.has(Feature::Roster) {
+        api.call(|| api.hub().user_profiles().get("me").doit()).await.ok().and_then(|profile| profile.id)
+    } else {
+        None
+    };
+    let profiles = features.has(Feature::Roster).then(|| Profiles::new(api.clone()));
+    Ok(Account { name: account.name.clone(), api, features, me, profiles })
+}
+
+/// Every course the accounts see that isn't skipped, each with the index of the account that archives it.
+///
+/// Listing fails for every account if it fails for one, rather than leave that one's courses out.
+pub async fn assigned_courses(config: &Config, accounts: &[Account], filter: &CourseFilter) -> classroom1::Result<(Vec<Course>, Vec<usize>)> {
+    let mut lists = Vec::new();
+    for account in accounts {
+        lists.push(list_courses(&account.api, filter).await?);
+    }
+    let me: Vec<Option<String>> = accounts.iter().map(|account| account.me.clone()).collect();
+    let mut assigned = accounts::assign(lists, &me);
+    assigned.retain(|(course, _)| !config.course(course.id.as_deref().unwrap_or_default()).skip);
+    Ok(assigned.into_iter().unzip())
+}
+
+/// The courses of each page, as indexes into `courses`, the one that names the page first. Without `merge` every
+/// course has a page of its own.
+pub async fn page_groups(config: &Config, accounts: &[Account], courses: &[Course], owners: &[usize], merge: bool) -> Vec<Vec<usize>> {
+    if !merge {
+        return (0..courses.len()).map(|index| vec![index]).collect();
+    }
+    let aliases = futures::future::join_all(courses.iter().zip(owners).map(|(course, &owner)| async move {
+        let id = course.id.clone().unwrap_or_default();
+        let aliases = list_aliases(&accounts[owner].api, &id).await.unwrap_or_else(|error| {
+            tracing::warn!(id, error = %error, "Failed to list course aliases");
+            Vec::new()
+        });
+        (id, aliases)
+    })).await.into_iter().collect();
+    let merge = config.courses.iter().filter_map(|(id, course)| Some((id.clone(), course.merge.clone()?))).collect();
+    merge_groups(courses, &aliases, &merge)
+}
+
+/// The invitations of every account with the roster feature, `None` when none has it. `taught[i]` is the courses
+/// account `i` archives and teaches, whose outstanding invitations are listed.
+pub async fn invitations(accounts: &[Account], courses: &[Course], taught: &[Vec<String>], output: &Path) -> Option<Invitations> {
+    let mut invitations: Option<Invitations> = None;
+    for (account, taught) in accounts.iter().zip(taught) {
+        if !account.features.has(Feature::Roster) {
+            continue;
+        }
+        let mut found = Invitations::fetch(&account.api, &Profiles::new(account.api.clone()), courses, taught, output).await;
+        if accounts.len() > 1 {
+            for row in &mut found.mine {
+                row.invitee = Some(account.name.clone());
+            }
+            for failed in &mut found.failed {
+                *failed = format!("{} ({})", failed, account.name);
+            }
+        }
+        let invitations = invitations.get_or_insert_with(Invitations::default);
+        invitations.mine.extend(found.mine);
+        invitations.outstanding.extend(found.outstanding);
+        invitations.failed.extend(found.failed);
+    }
+    invitations
+}
+
+/// The templates each account's pages are rendered with, in the order of `accounts`.
+///
+/// Names are looked up by the account whose page they're on, as only it may see them.
+pub fn templates(theme: &Theme, accounts: &[Account], cache: &CacheOptions, embeds: &EmbedOptions) -> tera::Result<Vec<Tera>> {
+    let mut tera = theme.templates()?;
+    let options = cache.clone();
+    tera.register_function("url_ok", move |args: &HashMap<String, Value>| {
+        if let Some(url) = args.get("url").and_then(|v| v.as_str()) {
+            Ok(Value::Bool(Handle::current().block_on(check_url(&options, url))))
+        } else {
+            Err(tera::Error::msg("No 'url' argument provided"))
+        }
+    });
+    if embeds.rich_embeds {
+        embeds::register(&mut tera);
+    }
+    Ok(accounts.iter().map(|account| {
+        let mut tera = tera.clone();
+        let profiles = account.profiles.clone();
+        tera.register_function("getusername", move |args: &HashMap<String, Value>| {
+            if let Some(id) = args.get("id").and_then(|v| v.as_str()) {
+                let name = profiles.as_ref().and_then(|profiles| Handle::current().block_on(profiles.name(id)));
+                Ok(Value::String(name.unwrap_or_else(|| "None".to_string())))
+            } else {
+                Err(tera::Error::msg("No 'id' argument provided"))
+            }
+        });
+        tera
+    }).collect())
+}
+
+/// What every page of the archive is written with.
+pub struct Site {
+    pub accounts: Vec<Account>,
+    /// Each account's templates, from `templates`
+    pub teras: Vec<Tera>,
+    pub theme: Theme,
+    pub redaction: Redaction,
+    pub output: PathBuf,
+    /// Roster setting of the courses without their own
+    pub students: StudentPrivacy,
+    pub embeds: EmbedOptions,
+    /// Where link cards are kept
+    pub cache: CacheOptions,
+}
+
+impl Site {
+    /// The page of `group`, from `page_groups`, archived by the account `owners` gives its first course.
+    pub fn page(&self, config: &Config, courses: &[Course], owners: &[usize], group: &[usize]) -> Page {
+        let course = &courses[group[0]];
+        let account = &self.accounts[owners[group[0]]];
+        let sections = group.iter().map(|&index| {
+            let section = &courses[index];
+            let students = config.course(section.id.as_deref().unwrap_or_default()).students.unwrap_or(self.students);
+            (section.clone(), students, self.accounts[owners[index]].clone())
+        }).collect();
+        Page {
+            course: course.clone(),
+            tera: self.teras[owners[group[0]]].clone(),
+            account: account.clone(),
+            origin: (self.accounts.len() > 1).then(|| account.name.clone()),
+            sections,
+            redaction: self.redaction.for_course(course.id.as_deref().unwrap_or_default()),
+            theme: self.theme.clone(),
+            output: self.output.clone(),
+            embeds: self.embeds.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+/// A course page, and everything it's archived with.
+#[derive(Clone)]
+pub struct Page {
+    pub course: Course,
+    pub tera: Tera,
+    /// The account the page is archived by
+    pub account: Account,
+    /// Its name, shown on the page when several accounts are archived
+    pub origin: Option<String>,
+    /// Every course merged into this page, this one first, with its roster setting and the account that sees it
+    pub sections: Vec<(Course, StudentPrivacy, Account)>,
+    pub redaction: Rules,
+    pub theme: Theme,
+    pub output: PathBuf,
+    pub embeds: EmbedOptions,
+    pub cache: CacheOptions,
+}
+
+/// What archiving a page leaves for the index and the next incremental run.
+pub struct Archived {
+    pub id: String,
+    pub stats: CourseStats,
+    /// Of each section, by its course ID
+    pub fingerprints: Vec<(String, Fingerprint)>,
+}
+
+impl Page {
+    /// Fetches the page's sections and writes the page, with its guardians and history pages when it has them.
+    /// `api` is the page account's, limited for this course, as `Scheduler::spawn_course` gives it.
+    pub async fn archive(self, api: Api, scheduler: &Scheduler) -> Result<Archived, Error> {
+        let span = tracing::info_span!("course", id = self.course.id.as_deref().unwrap_or_default(), name = self.course.name.as_deref().unwrap_or_default());
+        self.write(api, scheduler).instrument(span).await
+    }
+
+    async fn write(self, api: Api, scheduler: &Scheduler) -> Result<Archived, Error> {
+        let start_time = Instant::now();
+        let mut context = Context::new();
+        let id = self.course.id.clone().unwrap_or_default();
+        let name = self.course.name.clone().unwrap_or_default();
+        let mut parts = Vec::new();
+        let mut fingerprints = Vec::new();
+        let mut guardians_page = false;
+        for (section, students, account) in &self.sections {
+            let section_id = section.id.as_deref().unwrap_or_default();
+            // A section another account sees is fetched with that account, within a limit of its own
+            let api = if account.name == self.account.name { api.clone() } else { scheduler.course_api(&account.api) };
+            let mut data = fetch_course(&api, section_id, *students, &account.features).await;
+            let teaching = CourseStats::new(section, &data.teachers, account.me.as_deref(), 0, 0).teaching;
+            // Only a teacher's account is sent the whole class's answers
+            if teaching && account.features.has(Feature::Submissions) {
+                data.fetch_answers(&api, section_id).await;
+            }
+            // Guardians get a page of their own, only made for taught courses that archive the roster
+            if teaching
+                && account.features.has(Feature::Guardians)
+                && account.features.has(Feature::Roster)
+                && *students != StudentPrivacy::Omit
+            {
+                data.fetch_guardians(&api, section_id, *students).await;
+                guardians_page = true;
+            }
+            fingerprints.push((section_id.to_string(), Fingerprint::of(section, &data)));
+            parts.push(data);
+        }
+        let data = if parts.len() == 1 { parts.remove(0) } else { merge_data(parts) };
+        let mut stats = CourseStats::new(&self.course, &data.teachers, self.account.me.as_deref(), data.announcements.len(), data.coursework.len());
+        stats.account = self.origin.clone();
+        if self.sections.len() > 1 {
+            // Tags every post with the section it came from
+            let labels: HashMap<String, String> = self.sections.iter()
+                .map(|(section, _, _)| (section.id.clone().unwrap_or_default(), section_label(section)))
+                .collect();
+            context.insert("sections", &labels);
+            stats.sections = self.sections.iter().map(|(section, _, _)| section_label(section)).collect();
+        }
+        if guardians_page {
+            let mut page = Context::new();
+            page.insert("name", &name);
+            page.insert("guardians", &data.guardians);
+            page.insert("failed_sections", &data.failed.iter().filter(|section| **section == "guardians").collect::<Vec<_>>());
+            page.insert("course_page", &format!("{}.html", id));
+            let mut page = self.redaction.redact_context(page);
+            self.theme.insert(&mut page, "../");
+            self.render(scheduler, self.tera.clone(), "guardians.html", page, &format!("{}-guardians.html", id)).await?;
+            context.insert("guardians_page", &format!("{}-guardians.html", id));
+        }
+        // Kept already redacted, so the page is rendered as is
+        let mut history = CourseHistory::load(&self.output, &id);
+        history.record(&data, &self.redaction);
+        if let Err(error) = history.save(&self.output, &id) {
+            tracing::warn!(error = %error, "Failed to save the edit history");
+        }
+        let edited = history.pages();
+        if !edited.is_empty() {
+            let mut page = Context::new();
+            page.insert("name", &name);
+            page.insert("items", &edited);
+            page.insert("course_page", &format!("{}.html", id));
+            self.theme.insert(&mut page, "../");
+            self.render(scheduler, self.tera.clone(), "history.html", page, &format!("{}-history.html", id)).await?;
+            context.insert("history", &edited.iter().map(|item| item.id.as_str()).collect::<Vec<_>>());
+            context.insert("history_page", &format!("{}-history.html", id));
+        }
+        tracing::info!(elapsed = ?start_time.elapsed(), sections = %data.timings, "Pulled course data");
+        context.insert("name", &name);
+        context.insert("course_announcements", &data.announcements);
+        context.insert("coursework", &data.coursework);
+        context.insert("course_materials", &data.materials);
+        context.insert("teachers", &data.teachers);
+        context.insert("topics", &data.topics);
+        if let Some(students) = &data.students {
+            context.insert("students", students);
+        }
+        context.insert("answers", &data.answers);
+        context.insert("rich_embeds", &self.embeds.rich_embeds);
+        context.insert("failed_sections", &data.failed);
+        if let Some(origin) = &self.origin {
+            context.insert("account", origin);
+        }
+        // Gradebook settings come with the course itself, there is nothing extra to fetch
+        if let Some(gradebook) = &self.course.gradebook_settings {
+            context.insert("gradebook", gradebook);
+        }
+        // Authors are named while their IDs are still there to look up
+        let profiles = self.account.profiles.as_ref().filter(|_| self.redaction.hides_creator_ids());
+        if let Some(profiles) = profiles {
+            context = profiles.name_creators(context).await;
+        }
+        let mut context = self.redaction.redact_context(context);
+        self.theme.insert(&mut context, "../");
+        let mut tera = self.tera.clone();
+        // Cards are redacted by the page's own rules, so they're looked up per page
+        if self.embeds.rich_embeds {
+            embeds::register_cards(&mut tera, &self.cache, &self.redaction, self.embeds.card_images);
+        }
+        self.render(scheduler, tera, "course.html", context, &format!("{}.html", id)).await?;
+        tracing::info!(elapsed = ?start_time.elapsed(), "Rendered course page");
+        Ok(Archived { id, stats, fingerprints })
+    }
+
+    /// Renders `template` on the blocking pool into `file` under the output's courses/.
+    async fn render(&self, scheduler: &Scheduler, tera: Tera, template: &'static str, context: Context, file: &str) -> Result<(), Error> {
+        let buffer = scheduler.render(move || {
+            let mut buffer = Vec::new();
+            tera.render_to(template, &context, &mut buffer).map(|()| buffer)
+        }).await?;
+        File::create(self.output.join("courses").join(file))?.write_all(&buffer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::HISTORY_DIR;
+    use crate::mock::MockServer;
+    use crate::redact::FieldAction;
+    use crate::schedule::Concurrency;
+    use crate::theme::tests::bundled;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn pages_are_redacted_and_their_edits_kept() {
+        let server = MockServer::start().await;
+        server.route("/v1/courses/1/announcements", json!({"announcements": [{"id": "a", "text": "Questions to teacher@school.org"}]}));
+        server.route("/v1/courses/1/courseWork", json!({"courseWork": [{"id": "w", "title": "Essay"}]}));
+        server.route("/v1/courses/1/courseWorkMaterials", json!({"courseWorkMaterial": []}));
+        server.route("/v1/courses/1/topics", json!({"topic": []}));
+        let api = server.api();
+        let account = Account { name: "default".to_string(), api: api.clone(), features: Features::new([]), me: None, profiles: None };
+
+        let theme = bundled();
+        let output = std::env::temp_dir().join(format!("csee-archive-{}", std::process::id()));
+        std::fs::create_dir_all(output.join("courses")).unwrap();
+        let redaction: Redaction = toml::from_str("[default]\nemails = \"strip\"\n").unwrap();
+        assert_eq!(redaction.default.emails, FieldAction::Strip);
+        let site = Site {
+            teras: templates(&theme, std::slice::from_ref(&account), &CacheOptions::default(), &EmbedOptions::default()).unwrap(),
+            accounts: vec![account],
+            theme,
+            redaction,
+            output: output.clone(),
+            students: StudentPrivacy::Omit,
+            embeds: EmbedOptions::default(),
+            cache: CacheOptions::default(),
+        };
+        let courses = [Course { id: Some("1".to_string()), name: Some("Biology".to_string()), ..Default::default() }];
+
+        let scheduler = Scheduler::new(Concurrency::default());
+        let archived = site.page(&Config::default(), &courses, &[0], &[0]).archive(api, &scheduler).await.unwrap();
+        assert_eq!((archived.id.as_str(), archived.stats.announcements, archived.stats.coursework), ("1", 1, 1));
+        assert_eq!(archived.fingerprints.len(), 1);
+        let html = std::fs::read_to_string(output.join("courses/1.html")).unwrap();
+        assert!(html.contains("Questions to [email removed]"), "{}", html);
+        assert!(!html.contains("teacher@school.org"));
+        // The edit history is kept redacted too
+        let history = std::fs::read_to_string(output.join(HISTORY_DIR).join("1.json")).unwrap();
+        assert!(!history.contains("teacher@school.org"), "{}", history);
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
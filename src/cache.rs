@@ -0,0 +1,416 @@
+//! On-disk cache for API responses.
+//!
+//! The cache is a small proxy on 127.0.0.1 that the hub is pointed at, so every call builder goes through it
+//! without knowing. Responses are keyed by method, URL and account; recorded fixtures leave the account out so
+//! they replay under any token.
+
+use crate::loopback::{read_request, write_response, RawRequest};
+use crate::Hub;
+use classroom1::hyper::client::HttpConnector;
+use classroom1::hyper::header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH, RETRY_AFTER};
+use classroom1::hyper::{self, Body, Client, StatusCode};
+use classroom1::hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheMode {
+    /// Every request goes to Google
+    #[default]
+    Off,
+    /// Reuse responses younger than --cache-ttl, revalidate older ones by ETag, and cache new ones
+    On,
+    /// Serve only from the cache and never touch the network
+    Offline,
+    /// Send every request to Google and save the responses as test fixtures
+    Record,
+    /// Serve only from recorded fixtures
+    Replay,
+}
+
+impl CacheMode {
+    /// Whether requests are answered without the network, so no OAuth token is needed either.
+    pub fn is_offline(self) -> bool {
+        matches!(self, CacheMode::Offline | CacheMode::Replay)
+    }
+}
+
+#[derive(Clone, Debug, clap::Args)]
+pub struct CacheOptions {
+    /// Whether API responses are cached on disk, and how they are used
+    #[arg(long = "cache", value_enum, default_value = "off")]
+    pub mode: CacheMode,
+    /// Where cached responses (or recorded fixtures) are kept
+    #[arg(long, default_value = ".cache/http")]
+    pub cache_dir: PathBuf,
+    /// Seconds a cached response is used before it is revalidated
+    #[arg(long, default_value_t = 3600)]
+    pub cache_ttl: u64,
+    /// Name of the signed-in account, so accounts sharing a cache don't see each other's responses
+    #[arg(long, default_value = "default")]
+    pub account: String,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions { mode: CacheMode::Off, cache_dir: PathBuf::from(".cache/http"), cache_ttl: 3600, account: "default".to_string() }
+    }
+}
+
+/// One stored response, also the fixture format.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Entry {
+    method: String,
+    url: String,
+    status: u16,
+    etag: Option<String>,
+    content_type: Option<String>,
+    /// Only on errors, which are passed on but never stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_after: Option<String>,
+    /// Seconds since the Unix epoch when the response was fetched or last revalidated
+    fetched_at: u64,
+    body: String,
+}
+
+struct Store {
+    dir: PathBuf,
+    account: Option<String>,
+}
+
+impl Store {
+    fn new(options: &CacheOptions) -> Store {
+        let fixtures = matches!(options.mode, CacheMode::Record | CacheMode::Replay);
+        Store { dir: options.cache_dir.clone(), account: (!fixtures).then(|| options.account.clone()) }
+    }
+
+    /// `courses_1_announcements-3fa9c2e1.json`, readable enough to find a fixture by hand
+    fn path(&self, method: &str, url: &str) -> PathBuf {
+        let key = format!("{}\n{} {}", self.account.as_deref().unwrap_or_default(), method, url);
+        let hex: String = Sha256::digest(key.as_bytes()).iter().take(4).map(|byte| format!("{:02x}", byte)).collect();
+        let path = url.split("://").last().unwrap_or(url);
+        let path = path.split_once('/').map_or("", |(_, path)| path).split('?').next().unwrap_or_default();
+        let mut name: String = path.trim_start_matches("v1/").chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .take(80)
+            .collect();
+        if name.is_empty() {
+            name.push_str("root");
+        }
+        self.dir.join(format!("{}-{}.json", name, hex))
+    }
+
+    fn get(&self, method: &str, url: &str) -> Option<Entry> {
+        let text = fs::read_to_string(self.path(method, url)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn put(&self, entry: &Entry) {
+        let write = || -> io::Result<()> {
+            fs::create_dir_all(&self.dir)?;
+            fs::write(self.path(&entry.method, &entry.url), serde_json::to_string_pretty(entry)?)
+        };
+        if let Err(error) = write() {
+            tracing::warn!(url = entry.url.as_str(), error = %error, "Failed to cache response");
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+struct Proxy {
+    options: CacheOptions,
+    store: Store,
+    upstream: String,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+}
+
+/// Points `hub` at a cache in front of wherever it was sending requests. Does nothing when the cache is off.
+pub async fn attach(options: &CacheOptions, hub: &mut Hub) -> io::Result<()> {
+    if options.mode == CacheMode::Off {
+        return Ok(());
+    }
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/", listener.local_addr()?);
+    let upstream = hub.base_url(url);
+    let connector = HttpsConnectorBuilder::new().with_native_roots()?.https_or_http().enable_http1().build();
+    let proxy = Arc::new(Proxy {
+        options: options.clone(),
+        store: Store::new(options),
+        upstream: upstream.trim_end_matches('/').to_string(),
+        client: Client::builder().build(connector),
+    });
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve(stream, proxy.clone()));
+        }
+    });
+    Ok(())
+}
+
+async fn serve(mut stream: TcpStream, proxy: Arc<Proxy>) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+    let entry = proxy.respond(&request).await;
+    let reason = StatusCode::from_u16(entry.status).ok().and_then(|status| status.canonical_reason()).unwrap_or_default();
+    let headers: Vec<(String, String)> = [("Content-Type", &entry.content_type), ("ETag", &entry.etag), ("Retry-After", &entry.retry_after)].into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value.clone()?)))
+        .collect();
+    write_response(&mut stream, &format!("{} {}", entry.status, reason), &headers, entry.body.as_bytes()).await;
+}
+
+impl Proxy {
+    async fn respond(&self, request: &RawRequest) -> Entry {
+        let url = format!("{}{}", self.upstream, request.target);
+        // Only reads are cached, anything else always goes through
+        let cached = match request.method.as_str() {
+            "GET" => self.store.get(&request.method, &url),
+            _ => return self.forward(request, &url, None).await,
+        };
+        match self.options.mode {
+            CacheMode::Offline | CacheMode::Replay => cached.unwrap_or_else(|| miss(request, &url)),
+            CacheMode::Record | CacheMode::Off => self.refresh(request, &url, None).await,
+            CacheMode::On => match cached {
+                Some(cached) if now().saturating_sub(cached.fetched_at) < self.options.cache_ttl => cached,
+                cached => self.refresh(request, &url, cached).await,
+            },
+        }
+    }
+
+    /// Fetches `url`, revalidating `cached` by its ETag, and stores what comes back.
+    async fn refresh(&self, request: &RawRequest, url: &str, cached: Option<Entry>) -> Entry {
+        let etag = cached.as_ref().and_then(|cached| cached.etag.clone());
+        let fresh = self.forward(request, url, etag).await;
+        match cached {
+            Some(mut cached) if fresh.status == StatusCode::NOT_MODIFIED.as_u16() => {
+                tracing::debug!(url, "Cached response still valid");
+                cached.fetched_at = now();
+                self.store.put(&cached);
+                cached
+            }
+            // Better a stale page than none when Google can't be reached
+            Some(cached) if fresh.status == StatusCode::BAD_GATEWAY.as_u16() => cached,
+            _ => {
+                if fresh.status == StatusCode::OK.as_u16() {
+                    self.store.put(&fresh);
+                }
+                fresh
+            }
+        }
+    }
+
+    async fn forward(&self, request: &RawRequest, url: &str, etag: Option<String>) -> Entry {
+        let mut builder = hyper::Request::builder().method(request.method.as_str()).uri(url);
+        for (key, value) in &request.headers {
+            if !["host", "connection", "content-length", "if-none-match"].contains(&key.to_lowercase().as_str()) {
+                builder = builder.header(key, value);
+            }
+        }
+        if let Some(etag) = etag {
+            builder = builder.header(IF_NONE_MATCH, etag);
+        }
+        let sent = match builder.body(Body::from(request.body.clone())) {
+            Ok(upstream_request) => self.client.request(upstream_request).await.map_err(|error| error.to_string()),
+            Err(error) => Err(error.to_string()),
+        };
+        let response = match sent {
+            Ok(response) => response,
+            Err(error) => return error_entry(request, url, StatusCode::BAD_GATEWAY, "UNAVAILABLE", &error),
+        };
+        let header = |name| response.headers().get(name).and_then(|value: &hyper::header::HeaderValue| value.to_str().ok()).map(str::to_string);
+        let (status, etag, content_type) = (response.status().as_u16(), header(ETAG), header(CONTENT_TYPE));
+        let retry_after = header(RETRY_AFTER);
+        let body = match hyper::body::to_bytes(response.into_body()).await {
+            Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+            Err(error) => return error_entry(request, url, StatusCode::BAD_GATEWAY, "UNAVAILABLE", &error.to_string()),
+        };
+        Entry { method: request.method.clone(), url: url.to_string(), status, etag, content_type, retry_after, fetched_at: now(), body }
+    }
+}
+
+/// A Google-style JSON error, so the hub reports it like any other failed call.
+fn error_entry(request: &RawRequest, url: &str, status: StatusCode, code: &str, message: &str) -> Entry {
+    let body = json!({"error": {"code": status.as_u16(), "status": code, "message": message}});
+    Entry {
+        method: request.method.clone(),
+        url: url.to_string(),
+        status: status.as_u16(),
+        etag: None,
+        content_type: Some("application/json".to_string()),
+        retry_after: None,
+        fetched_at: now(),
+        body: body.to_string(),
+    }
+}
+
+fn miss(request: &RawRequest, url: &str) -> Entry {
+    tracing::warn!(url, "Not in the offline cache");
+    error_entry(request, url, StatusCode::NOT_FOUND, "NOT_FOUND", &format!("{} is not in the offline cache", url))
+}
+
+/// Whether `url` answers 200, for the `url_ok` template function. Goes through the cache like API requests do.
+pub async fn check_url(options: &CacheOptions, url: &str) -> bool {
+    let store = Store::new(options);
+    let cached = store.get("CHECK", url);
+    let fresh = |entry: &Entry| now().saturating_sub(entry.fetched_at) < options.cache_ttl;
+    match (options.mode, cached) {
+        (mode, cached) if mode.is_offline() => cached.is_some_and(|entry| entry.status == 200),
+        (CacheMode::On, Some(cached)) if fresh(&cached) => cached.status == 200,
+        (mode, _) => {
+            let status = match reqwest::get(url).await {
+                Ok(response) => response.status().as_u16(),
+                Err(_) => 0,
+            };
+            if mode != CacheMode::Off && status != 0 {
+                store.put(&Entry {
+                    method: "CHECK".to_string(),
+                    url: url.to_string(),
+                    status,
+                    etag: None,
+                    content_type: None,
+                    retry_after: None,
+                    fetched_at: now(),
+                    body: String::new(),
+                });
+            }
+            status == 200
+        }
+    }
+}
+
+/// What `fetch` made of `url`, kept under `kind` in the cache so it is only fetched once. Unlike API responses these
+/// never go stale and are kept whatever the mode; offline only what was kept is used. Nothing is kept when `fetch`
+/// fails (returns `None`), so it is tried again next time.
+pub async fn fetch_once<T, F>(options: &CacheOptions, kind: &str, url: &str, fetch: F) -> Option<T>
+where
+    T: Serialize + DeserializeOwned,
+    F: Future<Output = Option<T>>,
+{
+    let store = Store::new(options);
+    if let Some(kept) = store.get(kind, url).and_then(|entry| serde_json::from_str(&entry.body).ok()) {
+        return Some(kept);
+    }
+    if options.mode.is_offline() {
+        return None;
+    }
+    let value = fetch.await?;
+    store.put(&Entry {
+        method: kind.to_string(),
+        url: url.to_string(),
+        status: 200,
+        etag: None,
+        content_type: Some("application/json".to_string()),
+        retry_after: None,
+        fetched_at: now(),
+        body: serde_json::to_string(&value).ok()?,
+    });
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Api, Limits};
+    use crate::filter::{list_courses, CourseFilter};
+    use crate::mock::MockServer;
+
+    fn options(name: &str, mode: CacheMode) -> CacheOptions {
+        let cache_dir = std::env::temp_dir().join(format!("csee-cache-{}-{}", std::process::id(), name));
+        CacheOptions { mode, cache_dir, cache_ttl: 3600, account: "teacher@school.org".to_string() }
+    }
+
+    async fn server() -> MockServer {
+        let server = MockServer::start().await;
+        server.route("/v1/courses", json!({"courses": [{"id": "1", "name": "EN201"}]}));
+        server
+    }
+
+    async fn course_names(server: &MockServer, options: &CacheOptions) -> classroom1::Result<Vec<String>> {
+        let mut hub = server.hub();
+        attach(options, &mut hub).await.unwrap();
+        let api = Api::new(hub, Limits { requests_per_second: 0.0, max_retries: 0, ..Default::default() });
+        let courses = list_courses(&api, &CourseFilter::default()).await?;
+        Ok(courses.into_iter().filter_map(|course| course.name).collect())
+    }
+
+    #[tokio::test]
+    async fn reuses_fresh_responses() {
+        let server = server().await;
+        let options = options("fresh", CacheMode::On);
+        let _ = fs::remove_dir_all(&options.cache_dir);
+
+        assert_eq!(course_names(&server, &options).await.unwrap(), ["EN201"]);
+        assert_eq!(course_names(&server, &options).await.unwrap(), ["EN201"]);
+        assert_eq!(server.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn revalidates_with_etags() {
+        let server = server().await;
+        server.etag("/v1/courses", "\"v1\"");
+        let options = CacheOptions { cache_ttl: 0, ..options("etag", CacheMode::On) };
+        let _ = fs::remove_dir_all(&options.cache_dir);
+
+        course_names(&server, &options).await.unwrap();
+        assert_eq!(course_names(&server, &options).await.unwrap(), ["EN201"]);
+        assert_eq!(server.requests(), 2);
+        assert_eq!(server.not_modified(), 1);
+    }
+
+    #[tokio::test]
+    async fn offline_mode_never_touches_the_network() {
+        let server = server().await;
+        let online = options("offline", CacheMode::On);
+        let _ = fs::remove_dir_all(&online.cache_dir);
+        course_names(&server, &online).await.unwrap();
+
+        let offline = CacheOptions { mode: CacheMode::Offline, ..online.clone() };
+        assert_eq!(course_names(&server, &offline).await.unwrap(), ["EN201"]);
+        assert_eq!(server.requests(), 1);
+
+        // Another account's responses are not shared
+        let other = CacheOptions { account: "student@school.org".to_string(), ..offline };
+        assert!(course_names(&server, &other).await.is_err());
+        assert_eq!(server.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn recorded_fixtures_replay_under_any_account() {
+        let server = server().await;
+        let record = options("fixtures", CacheMode::Record);
+        let _ = fs::remove_dir_all(&record.cache_dir);
+        course_names(&server, &record).await.unwrap();
+
+        let fixture = fs::read_dir(&record.cache_dir).unwrap().next().unwrap().unwrap().path();
+        assert!(fixture.file_name().unwrap().to_str().unwrap().starts_with("courses-"));
+        let replay = CacheOptions { mode: CacheMode::Replay, account: "someone else".to_string(), ..record };
+        assert_eq!(course_names(&server, &replay).await.unwrap(), ["EN201"]);
+        assert_eq!(server.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn fetched_once_even_with_the_cache_off() {
+        let off = options("once", CacheMode::Off);
+        let _ = fs::remove_dir_all(&off.cache_dir);
+        let url = "https://example.org/page";
+        assert_eq!(fetch_once(&off, "CARD", url, async { None::<String> }).await, None);
+        assert_eq!(fetch_once(&off, "CARD", url, async { Some("first".to_string()) }).await.as_deref(), Some("first"));
+        assert_eq!(fetch_once(&off, "CARD", url, async { Some("second".to_string()) }).await.as_deref(), Some("first"));
+
+        let offline = CacheOptions { mode: CacheMode::Offline, ..off };
+        assert_eq!(fetch_once(&offline, "CARD", url, async { Some("fetched".to_string()) }).await.as_deref(), Some("first"));
+        assert_eq!(fetch_once(&offline, "CARD", "https://example.org/other", async { Some("fetched".to_string()) }).await, None::<String>);
+    }
+}
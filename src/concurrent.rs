@@ -1,180 +1,124 @@
-use std::time::Instant;
 extern crate google_classroom1 as classroom1;
-use classroom1::api::ListCoursesResponse;
-use classroom1::{Classroom, hyper, hyper_rustls};
-use hyper::{Body, Response};
-use oauth2::Scope;
-use serde_json::Value;
-use tera::{Tera, Context};
-use tokio::{task, runtime};
-use std::{fs::File, io::Write, collections::HashMap};
-use std::sync::{Arc, Mutex, mpsc};
-use tokio::runtime::{Builder, Runtime};
-
-#[tokio::main]
-async fn main() {
-
-    let secret = classroom1::oauth2::read_application_secret("credentials.json")
-        .await
-        .expect("client secret couldn't be read.");
-    let auth = classroom1::oauth2::InstalledFlowAuthenticator::builder(
-        secret,
-        classroom1::oauth2::InstalledFlowReturnMethod::HTTPRedirect,
-    )
-    .persist_tokens_to_disk("tokens.json")
-    .build()
-    .await
-    .expect("InstalledFlowAuthenticator failed to build");
-    let _scopes = vec![
-        Scope::new("https://www.googleapis.com/auth/drive.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.announcements.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.courses.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.coursework.students.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.coursework.me.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.courseworkmaterials.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.rosters.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.profile.emails".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.profile.photos".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.topics.readonly".to_string()),
-    ];
-    match auth.token(&_scopes).await {
-        Ok(_token) => (),
-        Err(e) => println!("error: {:?}", e),
-    }
-
-    let hub = Classroom::new(hyper::Client::builder().build(hyper_rustls::HttpsConnectorBuilder::new().with_native_roots().unwrap().https_or_http().enable_http1().build()), auth);
-    let response: (Response<Body>, ListCoursesResponse) = hub.courses().list().page_size(100).doit().await.unwrap();
-
-    let mut tera = Tera::new("../templates/**/*.html").unwrap();
-    let mut context = Context::new();
-    let mut buffer = Vec::new();
-    let course_list = response.1.courses.clone().unwrap();
-    tera.add_template_file("templates/courses.html", Some("course_list")).unwrap();
-    tera.add_template_file("templates/course.html", Some("course")).unwrap();
-    context.insert("courses", &course_list);
-    tera.render_to("course_list", &context, &mut buffer).unwrap();
-    let mut file = File::create("html/courses.html").expect("Failed to create file");
+use classroom1::api::Course;
+use clap::Parser;
+use csee::api::Limits;
+use csee::archive::{assigned_courses, connect, page_groups, templates, Error, Site};
+use csee::cache::CacheOptions;
+use csee::config::Config;
+use csee::daemon::RunLock;
+use csee::embeds::EmbedOptions;
+use csee::filter::CourseFilter;
+use csee::index::{index_context, redact_index, CourseStats};
+use csee::metrics::{Metrics, METRICS};
+use csee::redact::Redaction;
+use csee::roster::StudentPrivacy;
+use csee::schedule::{Concurrency, Scheduler};
+use csee::scopes::Features;
+use csee::telemetry::Logging;
+use csee::theme::ThemeOptions;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::{fs::{self, File}, io::Write, collections::HashMap};
 
-    let hub_arc = Arc::new(Mutex::new(hub.clone()));
+/// Archive every Google Classroom course as static HTML under html/
+#[derive(Parser)]
+struct Cli {
+    /// TOML file with the privacy redaction rules applied before anything is rendered
+    #[arg(long)]
+    redaction: Option<PathBuf>,
+    #[command(flatten)]
+    embeds: EmbedOptions,
+    #[command(flatten)]
+    features: Features,
+    #[command(flatten)]
+    theme: ThemeOptions,
+    #[command(flatten)]
+    limits: Limits,
+    #[command(flatten)]
+    concurrency: Concurrency,
+    #[command(flatten)]
+    cache: CacheOptions,
+    #[command(flatten)]
+    logging: Logging,
+}
 
-    tera.register_function("getusername", move |args: &HashMap<String, Value>| {
-        if let Some(id) = args.get("id").and_then(|v| v.as_str()) {
-            let hub_mutex = &hub_arc.lock().unwrap(); // Acquire the lock to access hub
-    
-            let user_profile = task::block_in_place(|| {
-                let runtime = Builder::new_current_thread()
-                    .enable_all()
-                    .build()
-                    .unwrap();
-                runtime.block_on(async move {
-                    hub_mutex.user_profiles().get(id).doit().await
-                })
-            });
-            match user_profile {
-                Ok(profile) => {
-                    let name = profile.1.name.unwrap().full_name.unwrap_or_else(|| "None".to_string());
-                    let name_value: Value = Value::String(name);
-                    Ok(name_value)
-                }
-                Err(_) => {
-                    let name = "None".to_string();
-                    let name_value: Value = Value::String(name);
-                    Ok(name_value)
-                },
-            }
-        } else {
-            Err(tera::Error::msg("No 'id' argument provided"))
-        }
+fn main() {
+    let (cli, config) = csee::config::parse::<Cli>();
+    cli.logging.init();
+    let runtime = cli.concurrency.runtime().expect("Failed to start the runtime");
+    // `html` may be writing the archive right now
+    let lock = RunLock::acquire(&config.lock_file()).unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
     });
+    if let Err(error) = runtime.block_on(archive(&cli, &config)) {
+        eprintln!("error: {}", error);
+        drop(lock);
+        std::process::exit(1);
+    }
+}
 
-    async fn check_url(url: String) -> bool {
-        match reqwest::get(&url).await {
-            Ok(response) => response.status() == reqwest::StatusCode::OK,
-            Err(_) => false,
-        }
+/// Archives every course on a page of its own, the way `html` does without its roster and merging options.
+async fn archive(cli: &Cli, config: &Config) -> Result<(), Error> {
+    let run_time = Instant::now();
+    let redaction = match &cli.redaction {
+        Some(path) => Redaction::load(path)?,
+        None => Redaction::default(),
+    };
+    let theme = cli.theme.load()?;
+    let mut signed_in = Vec::new();
+    for account in config.accounts() {
+        signed_in.push(connect(config, &account, &cli.features, &cli.limits, &cli.cache).await?);
     }
-    
-    // Register the Tera filter function
-    tera.register_function("url_ok", move |args: &HashMap<String, Value>| {
-        if let Some(url) = args.get("url").and_then(|v| v.as_str()) {
-            let url = url.to_string(); // Clone the URL for async closure
-    
-            // Create a channel for communication between threads
-            let (sender, receiver) = mpsc::channel();
-    
-            // Spawn a new thread to run the async block
-            std::thread::spawn(move || {
-                let runtime = Runtime::new().unwrap();
-                let result = runtime.block_on(async move {
-                    check_url(url).await
-                });
-                let _ = sender.send(result);
-            });
-    
-            // Wait for the result from the spawned thread
-            let result = receiver.recv().unwrap();
-    
-            Ok(Value::Bool(result))
-        } else {
-            Err(tera::Error::msg("No 'url' argument provided"))
-        }
-    });
-    
-    let threaded_rt = runtime::Builder::new_multi_thread()
-            .worker_threads(16)
-            .enable_all()
-            .build()
-            .unwrap();
-    let mut handles = vec![];
+    let (course_list, owners) = assigned_courses(config, &signed_in, &CourseFilter::default()).await?;
 
-    
-    file.write_all(&buffer).expect("Failed to write to file");
-    let total_duration= Instant::now();
-    for course in response.1.courses.unwrap() {
-        let hub_clone = hub.clone();
-        let tera_clone = tera.clone();
-        let handle = threaded_rt.spawn(async move {
-            let start_time = Instant::now();
-            let mut buffer: Vec<u8> = Vec::new();
-            let mut context = Context::new();
-            let string_id = course.clone().id.clone().unwrap();
-            let id = string_id.as_str();
-            println!("Pulling Data From {}", course.name.clone().unwrap());
-            
-            let hub_clone = hub_clone.clone();
-            let course_announcements = Some(hub_clone.courses().announcements_list(id).doit().await.unwrap().1.announcements.unwrap());
-            let course_work = Some(hub_clone.courses().course_work_list(id).doit().await.unwrap().1.course_work.clone().unwrap_or_else(Vec::new));
-            let course_materials = Some(hub_clone.courses().course_work_materials_list(id).doit().await.unwrap().1.course_work_material.clone().unwrap_or_default());
-            let name = Some(course.name.clone().unwrap_or_default());
-            let teachers = Some(hub_clone.courses().teachers_list(id).doit().await.unwrap().1.teachers.clone().unwrap_or_default());
-            let topics = Some(hub_clone.courses().topics_list(id).doit().await.unwrap().1.topic.clone().unwrap_or_default());
-            println!("Took {:?}", start_time.elapsed());
-            println!("Course: {}, {}", name.clone().unwrap(), id);
-            context.insert("name", &name.clone());
-            if course_announcements.is_some() {
-                context.insert("course_announcements", &course_announcements.clone().unwrap());
-            }
-            if course_work.is_some() {
-                context.insert("coursework", &course_work.clone().unwrap());
-            }
-            if course_materials.is_some() {
-                context.insert("course_materials", &course_materials.clone().unwrap());
+    let output = config.render.output.clone();
+    fs::create_dir_all(output.join("courses"))?;
+    theme.copy_static(&output)?;
+    let teras = templates(&theme, &signed_in, &cli.cache, &cli.embeds)?;
+    let groups = page_groups(config, &signed_in, &course_list, &owners, false).await;
+    let site = Site {
+        accounts: signed_in,
+        teras,
+        theme,
+        redaction,
+        output,
+        students: StudentPrivacy::Omit,
+        embeds: cli.embeds.clone(),
+        cache: cli.cache.clone(),
+    };
+
+    let scheduler = Scheduler::new(cli.concurrency.clone());
+    let tasks: Vec<_> = groups.iter().map(|group| {
+        let page = site.page(config, &course_list, &owners, group);
+        let scheduler = scheduler.clone();
+        scheduler.clone().spawn_course(&page.account.api.clone(), move |api| async move { page.archive(api, &scheduler).await })
+    }).collect();
+    let mut stats: HashMap<String, CourseStats> = HashMap::new();
+    for result in futures::future::join_all(tasks).await {
+        match result {
+            Ok(Ok(archived)) => {
+                stats.insert(archived.id, archived.stats);
             }
-            if teachers.is_some() {
-                context.insert("teachers", &teachers.clone().unwrap());
+            Ok(Err(error)) => {
+                tracing::error!(error = %error, "Failed to archive a course");
+                Metrics::add(&METRICS.course_failures, 1);
             }
-            if topics.is_some() {
-                context.insert("topics", &topics.clone().unwrap());
+            Err(error) => {
+                tracing::error!(error = %error, "Course task failed");
+                Metrics::add(&METRICS.course_failures, 1);
             }
-            tera_clone.render_to("course", &context, &mut buffer).unwrap();
-            let mut file = File::create(format!("html/courses/{}.html", id)).expect("Failed to create file");
-            file.write_all(&buffer).expect("Failed to write to file");
-            println!("Course: {}, {}\nRender Time: {:?}", course.name.clone().unwrap(), id, start_time.elapsed());
-        });
-        handles.push(handle);
-    }
-    for handle in handles {
-        handle.await.expect("Async thread failed");
+        }
     }
-    println!("Total Time {}", total_duration.elapsed().as_secs());
-}
\ No newline at end of file
+
+    // The index is rendered last so it can show what was pulled for each course
+    let mut pages: Vec<Course> = course_list.clone();
+    redact_index(&mut pages, &mut stats, &site.redaction);
+    let mut context = index_context(&pages, &stats);
+    site.theme.insert(&mut context, "");
+    let mut buffer = Vec::new();
+    site.teras[0].render_to("courses.html", &context, &mut buffer)?;
+    File::create(site.output.join("courses.html"))?.write_all(&buffer)?;
+    METRICS.log_summary(run_time.elapsed());
+    Ok(())
+}
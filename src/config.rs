@@ -0,0 +1,587 @@
+//! The archiver's configuration file, so deployments differ only by config.
+//!
+//! Settings are read from the file named by `--config`, else `$CSEE_CONFIG`, else `csee.toml` when it exists. Any
+//! setting outside `[courses]` can be overridden from the environment as `CSEE_<SECTION>_<KEY>`, e.g.
+//! `CSEE_FETCH_PAGE_SIZE=50` or `CSEE_AUTH_SCOPES='["https://www.googleapis.com/auth/classroom.courses.readonly"]'`.
+//! Values are read as TOML, falling back to a plain string.
+//!
+//! Settings that also have a command line flag only change that flag's default, so a flag given on the command line
+//! still wins.
+
+use crate::cache::CacheMode;
+use crate::daemon::Schedule;
+use crate::roster::StudentPrivacy;
+use crate::scopes::Feature;
+use clap::{Arg, ArgMatches, Command, ValueEnum};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+const DEFAULT_PATH: &str = "csee.toml";
+const ENV_PREFIX: &str = "CSEE_";
+const SECTIONS: [&str; 6] = ["auth", "daemon", "fetch", "render", "server", "theme"];
+
+/// ```toml
+/// [auth]
+/// credentials = "credentials.json"
+/// tokens = "tokens.json"
+/// redirect_url = "http://localhost:8080/auth/callback"
+///
+/// [fetch]
+/// page_size = 100
+/// requests_per_second = 5
+/// students = "pseudonymise"
+/// features = ["roster", "photos"]
+///
+/// [render]
+/// output = "html"
+/// timezone = "America/Los_Angeles"
+/// redaction = "redaction.toml"
+///
+/// [server]
+/// bind = "127.0.0.1:8080"
+/// pubsub_topic = "projects/my-project/topics/classroom"
+/// notification_token = "a-long-random-string"
+///
+/// [theme]
+/// dir = "themes/csee"
+///
+/// [daemon]
+/// full = "0 2 * * *"
+/// active = "*/30 7-18 * * 1-5"
+/// archived = "@weekly"
+///
+/// [[accounts]]
+/// name = "Maths"
+/// tokens = "tokens-maths.json"
+///
+/// [[accounts]]
+/// name = "English"
+/// tokens = "tokens-english.json"
+///
+/// [courses."581959133980"]
+/// skip = true
+///
+/// [courses."581959134012"]
+/// merge = "english-10"
+/// schedule = "*/10 * * * *"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub auth: AuthConfig,
+    pub daemon: DaemonConfig,
+    pub fetch: FetchConfig,
+    pub render: RenderConfig,
+    pub server: ServerConfig,
+    pub theme: ThemeConfig,
+    /// Accounts archived into the one site, in order of preference for courses several of them see. Without any,
+    /// the account signed in with `auth.tokens` is archived.
+    pub accounts: Vec<AccountConfig>,
+    /// Per-course settings by course ID
+    pub courses: HashMap<String, CourseConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// OAuth client secret downloaded from the Google Cloud console
+    pub credentials: PathBuf,
+    /// Where tokens are kept between runs
+    pub tokens: PathBuf,
+    /// Scopes to ask for on top of those the enabled features need
+    pub scopes: Vec<String>,
+    /// Where Google sends the browser back to after sign-in, served by the `test` binary
+    pub redirect_url: String,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            credentials: PathBuf::from("credentials.json"),
+            tokens: PathBuf::from("tokens.json"),
+            scopes: Vec::new(),
+            redirect_url: "http://localhost:8080/auth/callback".to_string(),
+        }
+    }
+}
+
+/// A Google account archived by `html`. The first run signs each one in, with the `auth.credentials` client.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    /// Shown with the account's courses, and keeps its cached responses apart from the other accounts'
+    pub name: String,
+    /// Where the account's tokens are kept between runs
+    pub tokens: PathBuf,
+}
+
+/// Schedules for `html --daemon`, read in `render.timezone` or else the machine's timezone.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Full refreshes, which fetch every course
+    pub full: Schedule,
+    /// Incremental updates of active courses, which only fetch the courses that changed
+    pub active: Option<Schedule>,
+    /// Incremental updates of archived courses. Without one they only change on full refreshes.
+    pub archived: Option<Schedule>,
+    /// Held during every run of `html`, `.csee.lock` in the output directory by default
+    pub lock_file: Option<PathBuf>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig { full: "0 2 * * *".parse().expect("a valid schedule"), active: None, archived: None, lock_file: None }
+    }
+}
+
+/// Defaults for the fetch flags of the same names.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    pub page_size: Option<i32>,
+    pub requests_per_second: Option<f64>,
+    pub max_in_flight: Option<usize>,
+    pub max_retries: Option<u32>,
+    pub backoff_ms: Option<u64>,
+    pub max_backoff_ms: Option<u64>,
+    pub max_courses: Option<usize>,
+    pub max_requests_per_course: Option<usize>,
+    pub worker_threads: Option<usize>,
+    pub render_threads: Option<usize>,
+    pub students: Option<StudentPrivacy>,
+    pub cache: Option<CacheMode>,
+    pub cache_dir: Option<PathBuf>,
+    pub cache_ttl: Option<u64>,
+    pub features: Option<Vec<Feature>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderConfig {
+    /// Directory the pages are written to, and that `export` reads from
+    pub output: PathBuf,
+    /// IANA name of the timezone dates are shown in
+    pub timezone: Option<String>,
+    pub merge_sections: Option<bool>,
+    /// Embed videos and forms, and show links as cards, on course pages
+    pub rich_embeds: Option<bool>,
+    /// Show link cards' images, loaded from the linked sites
+    pub card_images: Option<bool>,
+    /// Privacy redaction rules, for the server's refreshes as much as for `html`
+    pub redaction: Option<PathBuf>,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig { output: PathBuf::from("html"), timezone: None, merge_sections: None, rich_embeds: None, card_images: None, redaction: None }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    /// Cloud Pub/Sub topic Classroom publishes course changes to, with the notifications feature
+    pub pubsub_topic: Option<String>,
+    /// Deliveries to `/notifications` must carry it as `?token=`, so only the push subscription can post, and so must
+    /// calls to `/notifications/register` and `/invitations/refresh`, which otherwise only answer this machine.
+    /// Required with `pubsub_topic`
+    pub notification_token: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { bind: "127.0.0.1:8080".to_string(), pubsub_topic: None, notification_token: None }
+    }
+}
+
+/// Defaults for `--theme` and `--templates`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub dir: Option<PathBuf>,
+    pub templates: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CourseConfig {
+    /// Leave the course out of the archive
+    pub skip: bool,
+    /// Roster setting for this course instead of `--students`
+    pub students: Option<StudentPrivacy>,
+    /// With `--merge-sections`, courses given the same name here share one page, as if they shared an alias
+    pub merge: Option<String>,
+    /// Incremental updates of this course in daemon mode, instead of `daemon.active` or `daemon.archived`
+    pub schedule: Option<Schedule>,
+}
+
+/// Everything wrong with a configuration, reported together so it can be fixed in one go.
+#[derive(Debug)]
+pub struct ConfigError {
+    /// The file, or the environment
+    pub source: String,
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid configuration in {}", self.source)?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Loads the configuration file, if there is one, and the environment overrides, then validates the result.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let path = path.map(Path::to_path_buf)
+            .or_else(|| env::var_os("CSEE_CONFIG").map(PathBuf::from))
+            .or_else(|| Some(PathBuf::from(DEFAULT_PATH)).filter(|path| path.is_file()));
+        let text = match &path {
+            Some(path) => fs::read_to_string(path).map_err(|e| ConfigError {
+                source: path.display().to_string(),
+                problems: vec![format!("can't be read: {}", e)],
+            })?,
+            None => String::new(),
+        };
+        let source = path.map_or_else(|| "the defaults".to_string(), |path| path.display().to_string());
+        Config::parse(&source, &text, env::vars())
+    }
+
+    /// Parses `text`, applies the `CSEE_*` variables among `vars` and validates.
+    pub fn parse(source: &str, text: &str, vars: impl IntoIterator<Item = (String, String)>) -> Result<Config, ConfigError> {
+        let error = |source: &str, problem: String| ConfigError { source: source.to_string(), problems: vec![problem] };
+        // Parsed typed first, so mistakes in the file are reported with their line
+        toml::from_str::<Config>(text).map_err(|e| error(source, e.to_string()))?;
+        let mut table: toml::Table = toml::from_str(text).map_err(|e| error(source, e.to_string()))?;
+
+        let mut overridden = Vec::new();
+        for (name, raw) in vars {
+            let Some(setting) = name.strip_prefix(ENV_PREFIX).filter(|_| name != "CSEE_CONFIG") else {
+                continue;
+            };
+            let setting = setting.to_lowercase();
+            let Some((section, key)) = SECTIONS.iter()
+                .find_map(|section| Some((*section, setting.strip_prefix(section)?.strip_prefix('_')?)))
+            else {
+                return Err(error("the environment", format!("{} is not a setting, expected CSEE_<SECTION>_<KEY> with a section of {}", name, SECTIONS.join(", "))));
+            };
+            let value = toml::from_str::<toml::Table>(&format!("value = {}", raw))
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .unwrap_or(toml::Value::String(raw));
+            let section = table.entry(section).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            let Some(section) = section.as_table_mut() else {
+                return Err(error(source, format!("{} is not a table", name)));
+            };
+            section.insert(key.to_string(), value);
+            overridden.push(name);
+        }
+        let config: Config = toml::Value::Table(table).try_into().map_err(|e: toml::de::Error| {
+            error(&format!("the environment ({})", overridden.join(", ")), e.message().to_string())
+        })?;
+
+        let problems = config.problems();
+        if !problems.is_empty() {
+            let source = if overridden.is_empty() {
+                source.to_string()
+            } else {
+                format!("{} with {} from the environment", source, overridden.join(", "))
+            };
+            return Err(ConfigError { source, problems });
+        }
+        Ok(config)
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for scope in &self.auth.scopes {
+            if !scope.starts_with("https://www.googleapis.com/auth/") {
+                problems.push(format!("auth.scopes: {:?} is not a Google API scope", scope));
+            }
+        }
+        match oauth2::url::Url::parse(&self.auth.redirect_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => (),
+            Ok(_) => problems.push(format!("auth.redirect_url: {:?} is not an http(s) URL", self.auth.redirect_url)),
+            Err(e) => problems.push(format!("auth.redirect_url: {:?} is not a URL: {}", self.auth.redirect_url, e)),
+        }
+        let mut names = std::collections::HashSet::new();
+        for account in &self.accounts {
+            if account.name.trim().is_empty() {
+                problems.push("accounts: every account needs a name".to_string());
+            } else if !names.insert(account.name.as_str()) {
+                problems.push(format!("accounts: {:?} is named twice", account.name));
+            }
+        }
+        if self.server.bind.parse::<SocketAddr>().is_err() {
+            problems.push(format!("server.bind: {:?} is not an address and port like \"127.0.0.1:8080\"", self.server.bind));
+        }
+        if let Some(topic) = &self.server.pubsub_topic {
+            let parts: Vec<&str> = topic.split('/').collect();
+            if !matches!(parts[..], ["projects", project, "topics", name] if !project.is_empty() && !name.is_empty()) {
+                problems.push(format!("server.pubsub_topic: {:?} is not a topic like \"projects/my-project/topics/classroom\"", topic));
+            }
+            // Otherwise anyone who can reach the server could have it fetch courses over and over
+            if self.server.notification_token.as_deref().is_none_or(str::is_empty) {
+                problems.push("server.notification_token must be set with server.pubsub_topic".to_string());
+            }
+        }
+        if let Some(timezone) = &self.render.timezone {
+            if timezone.parse::<chrono_tz::Tz>().is_err() {
+                problems.push(format!("render.timezone: {:?} is not a timezone name like \"America/Los_Angeles\"", timezone));
+            }
+        }
+        let fetch = &self.fetch;
+        let at_least_one = [
+            // What the API is sent, so negative sizes count as none
+            ("page_size", fetch.page_size.map(|value| usize::try_from(value).unwrap_or(0))),
+            ("max_in_flight", fetch.max_in_flight),
+            ("max_courses", fetch.max_courses),
+            ("max_requests_per_course", fetch.max_requests_per_course),
+            ("worker_threads", fetch.worker_threads),
+            ("render_threads", fetch.render_threads),
+        ];
+        for (key, value) in at_least_one {
+            if value == Some(0) {
+                problems.push(format!("fetch.{} must be at least 1", key));
+            }
+        }
+        if fetch.requests_per_second.is_some_and(|rate| !rate.is_finite() || rate < 0.0) {
+            problems.push("fetch.requests_per_second must be 0 (no pacing) or more".to_string());
+        }
+        if let (Some(first), Some(longest)) = (fetch.backoff_ms, fetch.max_backoff_ms) {
+            if first > longest {
+                problems.push(format!("fetch.backoff_ms ({}) is longer than fetch.max_backoff_ms ({})", first, longest));
+            }
+        }
+        problems
+    }
+
+    /// Command line defaults this configuration sets, by argument ID.
+    fn defaults(&self) -> Vec<(&'static str, String)> {
+        fn name(value: impl ValueEnum) -> String {
+            value.to_possible_value().expect("no skipped variants").get_name().to_string()
+        }
+        let fetch = &self.fetch;
+        let defaults = [
+            ("page_size", fetch.page_size.map(|value| value.to_string())),
+            ("requests_per_second", fetch.requests_per_second.map(|value| value.to_string())),
+            ("max_in_flight", fetch.max_in_flight.map(|value| value.to_string())),
+            ("max_retries", fetch.max_retries.map(|value| value.to_string())),
+            ("backoff_ms", fetch.backoff_ms.map(|value| value.to_string())),
+            ("max_backoff_ms", fetch.max_backoff_ms.map(|value| value.to_string())),
+            ("max_courses", fetch.max_courses.map(|value| value.to_string())),
+            ("max_requests_per_course", fetch.max_requests_per_course.map(|value| value.to_string())),
+            ("worker_threads", fetch.worker_threads.map(|value| value.to_string())),
+            ("render_threads", fetch.render_threads.map(|value| value.to_string())),
+            ("students", fetch.students.map(name)),
+            ("mode", fetch.cache.map(name)),
+            ("cache_dir", fetch.cache_dir.as_ref().map(|dir| dir.display().to_string())),
+            ("cache_ttl", fetch.cache_ttl.map(|value| value.to_string())),
+            ("features", fetch.features.as_ref().map(|features| features.iter().copied().map(name).collect::<Vec<_>>().join(","))),
+            ("timezone", self.render.timezone.clone()),
+            ("merge_sections", self.render.merge_sections.map(|merge| merge.to_string())),
+            ("rich_embeds", self.render.rich_embeds.map(|rich| rich.to_string())),
+            ("card_images", self.render.card_images.map(|images| images.to_string())),
+            ("redaction", self.render.redaction.as_ref().map(|path| path.display().to_string())),
+            // `export` reads the archive the other binaries write
+            ("input", Some(self.render.output.display().to_string())),
+            ("theme", self.theme.dir.as_ref().map(|dir| dir.display().to_string())),
+            ("templates", self.theme.templates.as_ref().map(|dir| dir.display().to_string())),
+        ];
+        defaults.into_iter().filter_map(|(id, value)| Some((id, value?))).collect()
+    }
+
+    /// Sets the defaults of `command`'s arguments from this configuration. Arguments it doesn't have are skipped.
+    pub fn apply(&self, mut command: Command) -> Command {
+        for (id, value) in self.defaults() {
+            if command.get_arguments().any(|arg| arg.get_id() == id) {
+                command = command.mut_arg(id, |arg| match arg.get_value_delimiter() {
+                    Some(delimiter) => {
+                        let values: Vec<String> = value.split(delimiter).filter(|value| !value.is_empty()).map(str::to_string).collect();
+                        arg.default_values(values)
+                    }
+                    None => arg.default_value(value),
+                });
+            }
+        }
+        command
+    }
+
+    /// The accounts to archive: those configured, else the one signed in with `auth.tokens`.
+    pub fn accounts(&self) -> Vec<AccountConfig> {
+        if self.accounts.is_empty() {
+            return vec![AccountConfig { name: "default".to_string(), tokens: self.auth.tokens.clone() }];
+        }
+        self.accounts.clone()
+    }
+
+    /// The lock every `html` run holds while it writes the archive.
+    pub fn lock_file(&self) -> PathBuf {
+        self.daemon.lock_file.clone().unwrap_or_else(|| self.render.output.join(".csee.lock"))
+    }
+
+    /// The course's own settings, the defaults when it has none.
+    pub fn course(&self, id: &str) -> CourseConfig {
+        self.courses.get(id).cloned().unwrap_or_default()
+    }
+}
+
+fn with_config_flag(command: Command) -> Command {
+    command.arg(
+        Arg::new("config")
+            .long("config")
+            .value_parser(clap::value_parser!(PathBuf))
+            .help("Configuration file, instead of $CSEE_CONFIG or ./csee.toml"),
+    )
+}
+
+/// Parses the command line of a binary with its configuration applied. A bad configuration ends the process with
+/// the problems found, like a bad flag does.
+pub fn parse<C: clap::Parser>() -> (C, Config) {
+    let matches = with_config_flag(C::command()).get_matches();
+    let config = Config::load(matches.get_one::<PathBuf>("config").map(PathBuf::as_path)).unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        std::process::exit(2);
+    });
+    let matches: ArgMatches = config.apply(with_config_flag(C::command())).get_matches();
+    let cli = C::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    (cli, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Limits;
+    use crate::scopes::Features;
+    use clap::{CommandFactory, FromArgMatches, Parser};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        limits: Limits,
+        #[arg(long, value_enum, default_value = "omit")]
+        students: StudentPrivacy,
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn file_and_environment_set_flag_defaults() {
+        let text = "[fetch]\npage_size = 50\nrequests_per_second = 2.5\nstudents = \"full\"\n\n[courses.\"42\"]\nskip = true\n";
+        let config = Config::parse("csee.toml", text, vars(&[("CSEE_FETCH_MAX_RETRIES", "2"), ("HOME", "/root")])).unwrap();
+        assert!(config.course("42").skip);
+        assert!(!config.course("43").skip);
+
+        let command = config.apply(Cli::command());
+        let cli = Cli::from_arg_matches(&command.clone().get_matches_from(["csee"])).unwrap();
+        assert_eq!((cli.limits.page_size, cli.limits.requests_per_second, cli.limits.max_retries), (50, 2.5, 2));
+        assert_eq!(cli.students, StudentPrivacy::Full);
+        // Untouched settings keep their flag's default, and the command line still wins
+        assert_eq!(cli.limits.max_in_flight, 8);
+        let cli = Cli::from_arg_matches(&command.get_matches_from(["csee", "--page-size", "10"])).unwrap();
+        assert_eq!(cli.limits.page_size, 10);
+    }
+
+    #[test]
+    fn environment_values_are_toml_or_strings() {
+        let config = Config::parse("csee.toml", "", vars(&[
+            ("CSEE_SERVER_BIND", "0.0.0.0:80"),
+            ("CSEE_AUTH_SCOPES", r#"["https://www.googleapis.com/auth/classroom.courses.readonly"]"#),
+            ("CSEE_THEME_DIR", "themes/csee"),
+            ("CSEE_SERVER_PUBSUB_TOPIC", "projects/school/topics/classroom"),
+            ("CSEE_SERVER_NOTIFICATION_TOKEN", "s3cret"),
+        ])).unwrap();
+        assert_eq!(config.server.bind, "0.0.0.0:80");
+        assert_eq!(config.server.pubsub_topic.as_deref(), Some("projects/school/topics/classroom"));
+        assert_eq!(config.auth.scopes.len(), 1);
+        assert_eq!(config.theme.dir, Some(PathBuf::from("themes/csee")));
+        assert_eq!(config.render.output, PathBuf::from("html"));
+    }
+
+    #[test]
+    fn features_set_the_list_default() {
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            features: Features,
+        }
+        let parse = |features: &str| {
+            let config = Config::parse("csee.toml", "", vars(&[("CSEE_FETCH_FEATURES", features)])).unwrap();
+            Cli::from_arg_matches(&config.apply(Cli::command()).get_matches_from(["csee"])).unwrap().features
+        };
+        let features = parse(r#"["photos", "attachments"]"#);
+        assert!(features.has(Feature::Photos) && features.has(Feature::Attachments) && !features.has(Feature::Roster));
+        assert!(!parse("[]").has(Feature::Roster));
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let text = "[server]\nbind = \"localhost\"\npubsub_topic = \"classroom\"\n\n[render]\ntimezone = \"Mars/Olympus\"\n\n[fetch]\npage_size = -5\n";
+        let error = Config::parse("csee.toml", text, vars(&[("CSEE_AUTH_REDIRECT_URL", "callback")])).unwrap_err();
+        assert_eq!(error.problems.len(), 6, "{}", error);
+        let message = error.to_string();
+        assert!(message.starts_with("invalid configuration in csee.toml with CSEE_AUTH_REDIRECT_URL from the environment"), "{}", message);
+        for setting in ["server.bind", "server.pubsub_topic", "server.notification_token", "render.timezone", "fetch.page_size", "auth.redirect_url"] {
+            assert!(message.contains(setting), "{} missing from {}", setting, message);
+        }
+    }
+
+    #[test]
+    fn page_sizes_must_fit_the_api() {
+        let error = Config::parse("csee.toml", "[fetch]\npage_size = 3000000000\n", vars(&[])).unwrap_err();
+        assert!(error.to_string().contains("page_size"), "{}", error);
+    }
+
+    #[test]
+    fn schedules_are_checked_when_loaded() {
+        let text = "[daemon]\nactive = \"*/30 7-18 * * 1-5\"\n\n[courses.\"42\"]\nschedule = \"@hourly\"\n";
+        let config = Config::parse("csee.toml", text, vars(&[("CSEE_DAEMON_FULL", "0 3 * * 0")])).unwrap();
+        assert_eq!(config.daemon.full.to_string(), "0 3 * * 0");
+        assert!(config.daemon.active.is_some() && config.daemon.archived.is_none());
+        assert_eq!(config.course("42").schedule.map(|schedule| schedule.to_string()).as_deref(), Some("@hourly"));
+        assert_eq!(config.lock_file(), PathBuf::from("html/.csee.lock"));
+
+        let error = Config::parse("csee.toml", "[daemon]\nfull = \"every night\"\n", vars(&[])).unwrap_err();
+        assert!(error.to_string().contains("every night"), "{}", error);
+    }
+
+    #[test]
+    fn accounts_default_to_the_signed_in_one() {
+        let config = Config::parse("csee.toml", "[auth]\ntokens = \"mine.json\"\n", vars(&[])).unwrap();
+        let accounts = config.accounts();
+        assert_eq!((accounts.len(), accounts[0].tokens.as_path()), (1, Path::new("mine.json")));
+
+        let text = "[[accounts]]\nname = \"Maths\"\ntokens = \"a.json\"\n\n[[accounts]]\nname = \"English\"\ntokens = \"b.json\"\n";
+        let names: Vec<String> = Config::parse("csee.toml", text, vars(&[])).unwrap().accounts().into_iter().map(|account| account.name).collect();
+        assert_eq!(names, ["Maths", "English"]);
+        let error = Config::parse("csee.toml", &text.replace("English", "Maths"), vars(&[])).unwrap_err();
+        assert!(error.to_string().contains("named twice"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        let error = Config::parse("csee.toml", "[fetch]\npagesize = 10\n", vars(&[])).unwrap_err();
+        assert!(error.to_string().contains("pagesize"), "{}", error);
+        assert!(error.to_string().contains("line 2"), "{}", error);
+
+        let error = Config::parse("csee.toml", "", vars(&[("CSEE_OUTPUT", "site")])).unwrap_err();
+        assert_eq!(error.source, "the environment");
+        assert!(error.problems[0].contains("CSEE_OUTPUT"));
+
+        let error = Config::parse("csee.toml", "", vars(&[("CSEE_FETCH_STUDENTS", "everyone")])).unwrap_err();
+        assert!(error.source.contains("CSEE_FETCH_STUDENTS"), "{}", error);
+    }
+}
@@ -0,0 +1,339 @@
+//! Archiving on a schedule, for `html --daemon`.
+//!
+//! Full refreshes fetch every course. Between them, incremental runs only fetch the courses whose schedule is due and
+//! whose newest post changed since they were archived, which costs three single-item requests a course to find out.
+//! A deleted post that wasn't the newest goes unnoticed until the next full refresh.
+
+use crate::api::Api;
+use crate::config::{CourseConfig, DaemonConfig};
+use crate::fetch::CourseData;
+use classroom1::api::Course;
+use classroom1::chrono::{DateTime, Datelike, Duration, FixedOffset, Local, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Written next to stats.json, so a restarted daemon still knows what it archived
+pub const FINGERPRINTS_FILE: &str = "fingerprints.json";
+
+/// A five-field cron expression, `minute hour day-of-month month day-of-week`, or one of `@hourly`, `@daily`,
+/// `@weekly` and `@monthly`.
+///
+/// Fields take `*`, numbers, ranges like `1-5`, steps like `*/15` or `8-18/2`, and lists of those. Sunday is 0 or 7.
+/// As in cron, when both day fields are restricted a time matching either one matches.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Schedule {
+    text: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Schedule, String> {
+        let expanded = match text.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("{:?} is not a cron schedule like \"*/30 * * * *\"", text));
+        };
+        let mut weekday_bits = field(weekdays, 0, 7, "day of week")?;
+        // Sunday may be written as 7
+        if weekday_bits & 1 << 7 != 0 {
+            weekday_bits |= 1;
+        }
+        Ok(Schedule {
+            text: text.trim().to_string(),
+            minutes: field(minutes, 0, 59, "minute")?,
+            hours: field(hours, 0, 23, "hour")?,
+            days: field(days, 1, 31, "day of month")?,
+            months: field(months, 1, 12, "month")?,
+            weekdays: weekday_bits,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Schedule, String> {
+        text.parse()
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// One field as a bit set of the values it allows.
+fn field(text: &str, min: u32, max: u32, name: &str) -> Result<u64, String> {
+    let mut bits = 0;
+    for part in text.split(',') {
+        let invalid = || format!("{:?} is not a valid {} ({}-{})", part, name, min, max);
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0).ok_or_else(invalid)?),
+            None => (part, 1),
+        };
+        let number = |text: &str| text.parse::<u32>().ok().filter(|value| (min..=max).contains(value)).ok_or_else(invalid);
+        let (first, last) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((first, last)) => (number(first)?, number(last)?),
+                // `5/10` runs from 5 to the end, like `5-59/10`
+                None if step > 1 => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Schedule {
+    /// Whether the schedule fires in the minute of `time`.
+    pub fn matches(&self, time: &DateTime<FixedOffset>) -> bool {
+        self.minutes & 1 << time.minute() != 0 && self.hours & 1 << time.hour() != 0 && self.matches_day(time)
+    }
+
+    fn matches_day(&self, time: &DateTime<FixedOffset>) -> bool {
+        let day = self.days & 1 << time.day() != 0;
+        let weekday = self.weekdays & 1 << time.weekday().num_days_from_sunday() != 0;
+        let day = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            (false, true) => day,
+            (true, _) => weekday,
+        };
+        day && self.months & 1 << time.month() != 0
+    }
+
+    /// The first minute after `time` the schedule fires in, within the next four years (so February 29 is found).
+    pub fn next_after(&self, time: &DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+        let mut next = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let end = next + Duration::days(4 * 366);
+        while next < end {
+            if !self.matches_day(&next) || self.hours & 1 << next.hour() == 0 {
+                // Nothing fires in this hour, so skip to the next
+                next += Duration::minutes(60 - next.minute() as i64);
+            } else if self.minutes & 1 << next.minute() == 0 {
+                next += Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+/// The current time where schedules are read: `timezone`, else the machine's own.
+pub fn now_in(timezone: Option<chrono_tz::Tz>) -> DateTime<FixedOffset> {
+    match timezone {
+        Some(timezone) => Utc::now().with_timezone(&timezone).fixed_offset(),
+        None => Local::now().fixed_offset(),
+    }
+}
+
+/// Which schedule refreshes a course between full refreshes: its own, else the one for its state. `None` leaves it
+/// to full refreshes.
+pub fn course_schedule<'a>(daemon: &'a DaemonConfig, settings: &'a CourseConfig, course: &Course) -> Option<&'a Schedule> {
+    if settings.schedule.is_some() {
+        return settings.schedule.as_ref();
+    }
+    match course.course_state.as_deref() {
+        Some("ARCHIVED") => daemon.archived.as_ref(),
+        _ => daemon.active.as_ref(),
+    }
+}
+
+/// Held while a run writes the archive, so a scheduled run and one started by hand never overlap.
+///
+/// The file holds the process ID, and is removed when the lock is dropped. A lock left by a process that is gone is
+/// taken over, which is only detected on Linux; elsewhere a stale lock file has to be removed by hand.
+#[derive(Debug)]
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    pub fn acquire(path: &Path) -> io::Result<RunLock> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", std::process::id())?;
+                    return Ok(RunLock { path: path.to_path_buf() });
+                }
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                    let holder = fs::read_to_string(path).ok().and_then(|text| text.trim().parse::<u32>().ok());
+                    match holder {
+                        Some(pid) if !running(pid) => {
+                            tracing::warn!(path = %path.display(), pid, "Taking over a lock left by a process that is gone");
+                            fs::remove_file(path)?;
+                        }
+                        _ => {
+                            let holder = holder.map_or_else(|| "another run".to_string(), |pid| format!("process {}", pid));
+                            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is held by {}", path.display(), holder)));
+                        }
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} keeps being taken", path.display())))
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn running(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
+
+/// The newest change to a course and its posts, compared between runs to find the courses worth fetching again.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub course: Option<DateTime<Utc>>,
+    pub posts: Option<DateTime<Utc>>,
+}
+
+impl Fingerprint {
+    /// From a course's fully fetched data.
+    pub fn of(course: &Course, data: &CourseData) -> Fingerprint {
+        let posts = data.announcements.iter().map(|item| item.update_time)
+            .chain(data.coursework.iter().map(|item| item.update_time))
+            .chain(data.materials.iter().map(|item| item.update_time))
+            .max()
+            .flatten();
+        Fingerprint { course: course.update_time, posts }
+    }
+
+    /// Asks Classroom for only the most recently updated post of each kind.
+    pub async fn probe(api: &Api, course: &Course) -> classroom1::Result<Fingerprint> {
+        let id = course.id.as_deref().unwrap_or_default();
+        let newest = "updateTime desc";
+        let (announcements, coursework, materials) = tokio::try_join!(
+            api.call(|| api.hub().courses().announcements_list(id).order_by(newest).page_size(1).doit()),
+            api.call(|| api.hub().courses().course_work_list(id).order_by(newest).page_size(1).doit()),
+            api.call(|| api.hub().courses().course_work_materials_list(id).order_by(newest).page_size(1).doit()),
+        )?;
+        let posts = announcements.announcements.unwrap_or_default().into_iter().map(|item| item.update_time)
+            .chain(coursework.course_work.unwrap_or_default().into_iter().map(|item| item.update_time))
+            .chain(materials.course_work_material.unwrap_or_default().into_iter().map(|item| item.update_time))
+            .max()
+            .flatten();
+        Ok(Fingerprint { course: course.update_time, posts })
+    }
+}
+
+/// Fingerprints of the courses in `output`, by course ID. Empty when there are none yet.
+pub fn load_fingerprints(output: &Path) -> HashMap<String, Fingerprint> {
+    fs::read(output.join(FINGERPRINTS_FILE)).ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_fingerprints(output: &Path, fingerprints: &HashMap<String, Fingerprint>) -> io::Result<()> {
+    fs::write(output.join(FINGERPRINTS_FILE), serde_json::to_vec_pretty(fingerprints)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use classroom1::chrono::TimeZone;
+    use serde_json::json;
+
+    fn at(text: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(text).unwrap()
+    }
+
+    #[test]
+    fn schedules_parse_like_cron() {
+        let schedule: Schedule = "*/15 8-18 * * 1-5".parse().unwrap();
+        assert!(schedule.matches(&at("2024-03-04T08:45:00-08:00")));
+        assert!(!schedule.matches(&at("2024-03-04T08:50:00-08:00")));
+        // A Sunday
+        assert!(!schedule.matches(&at("2024-03-03T08:45:00-08:00")));
+        assert_eq!(schedule.next_after(&at("2024-03-01T18:50:00-08:00")), Some(at("2024-03-04T08:00:00-08:00")));
+
+        // Either day field matches once both are restricted, and 7 is Sunday
+        let schedule: Schedule = "0 3 1 * 7".parse().unwrap();
+        assert!(schedule.matches(&at("2024-03-01T03:00:00Z")));
+        assert!(schedule.matches(&at("2024-03-03T03:00:00Z")));
+        assert!(!schedule.matches(&at("2024-03-02T03:00:00Z")));
+
+        let leap: Schedule = "0 0 29 2 *".parse().unwrap();
+        assert_eq!(leap.next_after(&at("2024-03-01T00:00:00Z")), Some(at("2028-02-29T00:00:00Z")));
+        assert_eq!("@daily".parse::<Schedule>().unwrap().next_after(&at("2024-03-01T12:34:56Z")), Some(at("2024-03-02T00:00:00Z")));
+
+        for bad in ["* * * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "a b c d e"] {
+            assert!(bad.parse::<Schedule>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn the_lock_is_exclusive_until_dropped() {
+        let path = std::env::temp_dir().join(format!("csee-lock-{}", std::process::id())).join("run.lock");
+        let lock = RunLock::acquire(&path).unwrap();
+        let error = RunLock::acquire(&path).unwrap_err();
+        assert!(error.to_string().contains(&format!("process {}", std::process::id())), "{}", error);
+        drop(lock);
+        assert!(!path.exists());
+
+        // Left by a process that can't be running
+        fs::write(&path, format!("{}\n", u32::MAX)).unwrap();
+        if cfg!(target_os = "linux") {
+            drop(RunLock::acquire(&path).unwrap());
+        }
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_probe_matches_a_full_fetch() {
+        let server = MockServer::start().await;
+        server.route("/v1/courses/1/announcements", json!({"announcements": [{"id": "a", "updateTime": "2024-03-02T10:00:00Z"}]}));
+        server.route("/v1/courses/1/courseWork", json!({"courseWork": [{"id": "w", "updateTime": "2024-03-04T10:00:00Z"}]}));
+        server.route("/v1/courses/1/courseWorkMaterials", json!({}));
+        let api = server.api();
+        let course = Course { id: Some("1".to_string()), ..Default::default() };
+
+        let probed = Fingerprint::probe(&api, &course).await.unwrap();
+        assert_eq!(probed.posts, Some(Utc.with_ymd_and_hms(2024, 3, 4, 10, 0, 0).unwrap()));
+        let data = crate::fetch::fetch_course(&api, "1", crate::roster::StudentPrivacy::Omit, &crate::scopes::Features::new([])).await;
+        assert_eq!(Fingerprint::of(&course, &data), probed);
+    }
+}
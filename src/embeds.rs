@@ -0,0 +1,384 @@
+//! Rich embeds for course materials: YouTube videos and Google Forms in frames, and links as Open-Graph cards.
+//!
+//! Frames are only ever pointed at URLs built here from a validated ID, never at what the API returned, and videos
+//! use YouTube's privacy-enhanced domain. Link cards are fetched once and kept in the response cache; when a card
+//! can't be had (offline, say) the template falls back to the plain link.
+//!
+//! Links are whatever a course's posts say, so a card is only fetched from a public address, redirects included,
+//! and its text is redacted like the rest of the page. Its image is loaded from the linked site by every visitor,
+//! so it is only shown with `--card-images`.
+
+use crate::cache::{fetch_once, CacheOptions};
+use crate::redact::{Roles, Rules};
+use regex::Regex;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tera::Tera;
+use tokio::runtime::Handle;
+
+lazy_static::lazy_static! {
+    static ref ID: Regex = Regex::new(r"^[A-Za-z0-9_-]+$").unwrap();
+    static ref META: Regex = Regex::new(r"(?is)<meta\b[^>]*>").unwrap();
+    static ref ATTRIBUTE: Regex = Regex::new(r#"(?is)([a-z:_-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    static ref TITLE: Regex = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+    /// Shared by every card, so pages to the same site reuse its connections
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .dns_resolver(PublicOnly)
+        .redirect(Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if public_url(attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.error("redirected to a private address")
+            }
+        }))
+        // A proxy would resolve the name itself, past the check
+        .no_proxy()
+        .build()
+        .expect("the link card client has no settings that can fail");
+}
+
+/// Only the start of a page is read for its card, where the `<head>` is
+const MAX_PAGE_BYTES: usize = 512 * 1024;
+const MAX_DESCRIPTION: usize = 300;
+const MAX_REDIRECTS: usize = 5;
+
+/// Rich embeds on course pages.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct EmbedOptions {
+    /// Embed YouTube videos and Google Forms, and show links as cards with the page's Open-Graph title.
+    /// Cards are fetched once and kept in the cache dir; links without one stay plain.
+    #[arg(long)]
+    pub rich_embeds: bool,
+    /// Show link cards' images too. Each is loaded from the linked site by whoever views the page.
+    #[arg(long)]
+    pub card_images: bool,
+}
+
+/// The privacy-enhanced embed URL for a YouTube video link, e.g. a `youtubeVideo` material's `alternateLink`.
+pub fn youtube(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    let host = host.strip_prefix("www.").or_else(|| host.strip_prefix("m.")).unwrap_or(host);
+    let mut path = url.path_segments()?;
+    let id = match (host, path.next()) {
+        ("youtu.be", Some(id)) => id.to_string(),
+        ("youtube.com", Some("watch")) => url.query_pairs().find(|(key, _)| key == "v")?.1.into_owned(),
+        ("youtube.com", Some("embed" | "shorts" | "live")) => path.next()?.to_string(),
+        _ => return None,
+    };
+    (id.len() == 11 && ID.is_match(&id)).then(|| format!("https://www.youtube-nocookie.com/embed/{}", id))
+}
+
+/// The embeddable URL of a Google Form, from its `formUrl`.
+pub fn form(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    if url.scheme() != "https" || url.host_str()? != "docs.google.com" {
+        return None;
+    }
+    let path: Vec<&str> = url.path_segments()?.collect();
+    // Published forms are under /forms/d/e/<id>, the editor's links under /forms/d/<id>
+    let id = match path.as_slice() {
+        ["forms", "d", "e", id, ..] => format!("e/{}", id),
+        ["forms", "d", id, ..] if *id != "e" => id.to_string(),
+        _ => return None,
+    };
+    ID.is_match(id.trim_start_matches("e/")).then(|| format!("https://docs.google.com/forms/d/{}/viewform?embedded=true", id))
+}
+
+/// What a page says about itself in its Open-Graph tags, for showing a link as a card.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkCard {
+    pub title: String,
+    pub description: Option<String>,
+    /// Only ever an http(s) URL
+    pub image: Option<String>,
+    /// `og:site_name`, or the page's host
+    pub site: String,
+}
+
+impl LinkCard {
+    /// Reads the card from the HTML of the page at `url`. Pages without a title don't get one.
+    pub fn parse(html: &str, url: &Url) -> Option<LinkCard> {
+        let mut tags: HashMap<String, String> = HashMap::new();
+        for meta in META.find_iter(html) {
+            let attributes: HashMap<String, String> = ATTRIBUTE.captures_iter(meta.as_str())
+                .map(|captures| {
+                    let value = captures.get(2).or_else(|| captures.get(3)).map_or("", |value| value.as_str());
+                    (captures[1].to_ascii_lowercase(), unescape(value))
+                })
+                .collect();
+            let name = attributes.get("property").or_else(|| attributes.get("name"));
+            if let (Some(name), Some(content)) = (name, attributes.get("content")) {
+                let content = content.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !content.is_empty() {
+                    tags.entry(name.to_ascii_lowercase()).or_insert(content);
+                }
+            }
+        }
+        let title = tags.remove("og:title")
+            .or_else(|| Some(unescape(TITLE.captures(html)?[1].trim())).filter(|title| !title.is_empty()))?;
+        let description = tags.remove("og:description").or_else(|| tags.remove("description")).map(|description| {
+            match description.char_indices().nth(MAX_DESCRIPTION) {
+                Some((end, _)) => format!("{}…", description[..end].trim_end()),
+                None => description,
+            }
+        });
+        // Relative images are resolved against the page, anything but http(s) is dropped
+        let image = tags.remove("og:image")
+            .and_then(|image| url.join(&image).ok())
+            .filter(|image| matches!(image.scheme(), "http" | "https"))
+            .map(String::from);
+        let site = tags.remove("og:site_name").or_else(|| url.host_str().map(str::to_string)).unwrap_or_default();
+        Some(LinkCard { title, description, image, site })
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// The card for `url`, fetched the first time it's asked for and from the cache after that.
+pub async fn link_card(options: &CacheOptions, url: &str) -> Option<LinkCard> {
+    let page = Url::parse(url).ok().filter(public_url)?;
+    let card = fetch_once(options, "CARD", url, async {
+        // Pages that answer are kept, card or not; those that can't be reached are tried again next time
+        match fetch_page(&page).await {
+            Ok(html) => Some(html.and_then(|html| LinkCard::parse(&html, &page))),
+            Err(error) => {
+                tracing::warn!(url, error = %error, "Failed to fetch the link's page for its card");
+                None
+            }
+        }
+    }).await;
+    card.flatten()
+}
+
+/// The start of the page's HTML, or `None` when it isn't an HTML page.
+async fn fetch_page(url: &Url) -> reqwest::Result<Option<String>> {
+    let mut response = CLIENT.get(url.clone()).header(reqwest::header::ACCEPT, "text/html").send().await?.error_for_status()?;
+    let html = response.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("html"));
+    if !html {
+        return Ok(None);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_PAGE_BYTES {
+            break;
+        }
+    }
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// Whether a card may be fetched from `url`: http(s), and not at an address of the machine or its network. Names
+/// are checked once they're resolved, by `PublicOnly`.
+fn public_url(url: &Url) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    // Addresses are bracketed in IPv6 URLs
+    match url.host_str().map(|host| host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>()) {
+        Some(Ok(ip)) => is_public(ip),
+        Some(Err(_)) => true,
+        None => false,
+    }
+}
+
+/// Whether `ip` is on the internet, rather than the machine itself, a private network or a reserved range.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Carrier-grade NAT, protocol assignments, benchmarking and the old class E
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (b == 18 || b == 19))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            // IPv4 addresses in IPv6 clothing, mapped or through NAT64
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [.., high, low] = segments;
+                return is_public(IpAddr::V4(((u32::from(high) << 16) | u32::from(low)).into()));
+            }
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local, link-local and documentation
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+                || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+        }
+    }
+}
+
+/// Resolves names as the system does, failing those with any address that isn't public, so a link or a redirect
+/// can't have the archiver fetch from the machines around it.
+struct PublicOnly;
+
+impl Resolve for PublicOnly {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+                return Err(format!("{} is not at a public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn url_arg(args: &HashMap<String, Value>) -> tera::Result<&str> {
+    args.get("url").and_then(|v| v.as_str()).ok_or_else(|| tera::Error::msg("No 'url' argument provided"))
+}
+
+/// Registers `youtube_embed(url=)` and `form_embed(url=)`, each `false` for what can't be embedded.
+pub fn register(tera: &mut Tera) {
+    tera.register_function("youtube_embed", |args: &HashMap<String, Value>| {
+        Ok(youtube(url_arg(args)?).map_or(Value::Bool(false), Value::String))
+    });
+    tera.register_function("form_embed", |args: &HashMap<String, Value>| {
+        Ok(form(url_arg(args)?).map_or(Value::Bool(false), Value::String))
+    });
+}
+
+/// Registers `link_card(url=)`, `false` for links without a card. The card's text is redacted by the page's `rules`,
+/// and its image left out unless `images`.
+pub fn register_cards(tera: &mut Tera, cache: &CacheOptions, rules: &Rules, images: bool) {
+    let cache = cache.clone();
+    let rules = rules.clone();
+    tera.register_function("link_card", move |args: &HashMap<String, Value>| {
+        let Some(mut card) = Handle::current().block_on(link_card(&cache, url_arg(args)?)) else {
+            return Ok(Value::Bool(false));
+        };
+        if !images {
+            card.image = None;
+        }
+        let mut card = serde_json::to_value(card).map_err(tera::Error::json)?;
+        rules.redact_value(&mut card, &Roles::default());
+        Ok(card)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_known_players_are_embedded() {
+        let embed = Some("https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ".to_string());
+        assert_eq!(youtube("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42"), embed);
+        assert_eq!(youtube("https://youtu.be/dQw4w9WgXcQ"), embed);
+        assert_eq!(youtube("https://m.youtube.com/shorts/dQw4w9WgXcQ"), embed);
+        assert_eq!(youtube("https://www.youtube.com/watch?v=dQw4w9WgXcQ\"onload=\"x"), None);
+        assert_eq!(youtube("https://evil.example/embed/dQw4w9WgXcQ"), None);
+
+        assert_eq!(form("https://docs.google.com/forms/d/e/1FAIpQLSf_abc/viewform?usp=sf_link").as_deref(),
+            Some("https://docs.google.com/forms/d/e/1FAIpQLSf_abc/viewform?embedded=true"));
+        assert_eq!(form("https://docs.google.com/forms/d/1abc-DEF/edit").as_deref(),
+            Some("https://docs.google.com/forms/d/1abc-DEF/viewform?embedded=true"));
+        assert_eq!(form("https://docs.google.com/document/d/1abc/edit"), None);
+        assert_eq!(form("http://docs.google.com/forms/d/1abc/edit"), None);
+    }
+
+    #[test]
+    fn cards_are_read_from_open_graph_tags() {
+        let page = Url::parse("https://example.org/articles/1").unwrap();
+        let html = r#"<html><head><title>Fallback</title>
+            <meta property="og:title" content="Tom &amp; Jerry">
+            <meta content='A  cat
+               and a mouse' name="description">
+            <meta property="og:image" content="/images/1.png">
+            </head></html>"#;
+        assert_eq!(LinkCard::parse(html, &page), Some(LinkCard {
+            title: "Tom & Jerry".to_string(),
+            description: Some("A cat and a mouse".to_string()),
+            image: Some("https://example.org/images/1.png".to_string()),
+            site: "example.org".to_string(),
+        }));
+
+        let html = r#"<title> Just a title </title><meta property="og:image" content="javascript:alert(1)">"#;
+        let card = LinkCard::parse(html, &page).unwrap();
+        assert_eq!((card.title.as_str(), card.image), ("Just a title", None));
+        assert_eq!(LinkCard::parse("<p>No head</p>", &page), None);
+    }
+
+    #[test]
+    fn cards_are_only_fetched_from_public_addresses() {
+        for url in ["http://127.0.0.1/", "http://10.1.2.3:8080/", "http://169.254.169.254/latest/meta-data/", "http://100.64.0.1/",
+            "http://0.0.0.0/", "http://[::1]/", "http://[fd00::1]/", "http://[fe80::1]/", "http://[::ffff:192.168.0.1]/",
+            "http://[64:ff9b::a00:1]/", "file:///etc/passwd"] {
+            assert!(!public_url(&Url::parse(url).unwrap()), "{}", url);
+        }
+        for url in ["https://example.org/", "http://93.184.216.34/", "http://[2606:2800:220:1::]/"] {
+            assert!(public_url(&Url::parse(url).unwrap()), "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn names_of_private_addresses_are_not_resolved() {
+        let error = PublicOnly.resolve("localhost".parse().unwrap()).await.err().unwrap();
+        assert!(error.to_string().contains("not at a public address"), "{}", error);
+        assert!(link_card(&CacheOptions::default(), "http://127.0.0.1:9/").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn cards_are_redacted_and_images_opt_in() {
+        let cache_dir = std::env::temp_dir().join(format!("csee-cards-{}", std::process::id()));
+        let cache = CacheOptions { cache_dir: cache_dir.clone(), ..Default::default() };
+        let url = "https://example.org/contact";
+        let card = LinkCard {
+            title: "Contact".to_string(),
+            description: Some("Write to office@school.org".to_string()),
+            image: Some("https://example.org/logo.png".to_string()),
+            site: "example.org".to_string(),
+        };
+        fetch_once(&cache, "CARD", url, async { Some(Some(card)) }).await;
+        let offline = CacheOptions { mode: crate::cache::CacheMode::Offline, ..cache };
+
+        let rules = Rules { emails: crate::redact::FieldAction::Strip, ..Default::default() };
+        let render = |images: bool| {
+            let mut tera = Tera::default();
+            tera.add_raw_template("card", "{{ link_card(url=url) | json_encode() | safe }}").unwrap();
+            register_cards(&mut tera, &offline, &rules, images);
+            let mut context = tera::Context::new();
+            context.insert("url", url);
+            tokio::task::spawn_blocking(move || tera.render("card", &context).unwrap())
+        };
+        let card: Value = serde_json::from_str(&render(false).await.unwrap()).unwrap();
+        assert_eq!(card["description"], "Write to [email removed]");
+        assert_eq!(card["image"], Value::Null);
+        let card: Value = serde_json::from_str(&render(true).await.unwrap()).unwrap();
+        assert_eq!(card["image"], "https://example.org/logo.png");
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+extern crate google_classroom1 as classroom1;
+use classroom1::api::Course;
+use classroom1::chrono;
+use clap::{Parser, ValueEnum};
+use csee::index::{index_context, CourseStats, STATS_FILE};
+use csee::theme::ThemeOptions;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Package an archived `html/` tree into a single bundle that can be handed to teachers.
+#[derive(Parser)]
+#[command(name = "export")]
+struct Cli {
+    /// Course IDs to include. Every archived course is exported when none are given.
+    courses: Vec<String>,
+    /// Bundle format
+    #[arg(long, value_enum, default_value = "zip")]
+    format: Format,
+    /// Where to write the bundle. Defaults to `csee-export.zip` or `csee-export.tar.zst`.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Archive directory written by the `html` binary
+    #[arg(long, default_value = "html")]
+    input: PathBuf,
+    /// Theme used to render the bundle's course list, normally the one the archive was made with
+    #[command(flatten)]
+    theme: ThemeOptions,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Zip,
+    #[value(name = "tar.zst")]
+    TarZst,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::TarZst => "tar.zst",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    generated_at: String,
+    courses: Vec<ManifestCourse>,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize)]
+struct ManifestCourse {
+    id: String,
+    name: String,
+    page: String,
+}
+
+#[derive(Serialize)]
+struct ManifestFile {
+    path: String,
+    size: usize,
+    sha256: String,
+}
+
+/// Bundle contents by '/'-separated path
+type Entries = BTreeMap<String, Vec<u8>>;
+
+// Shared files at the top of html/ that every page links to relatively
+const ASSET_EXTENSIONS: [&str; 6] = ["css", "png", "jpg", "jpeg", "gif", "svg"];
+
+fn main() -> io::Result<()> {
+    let (cli, _config) = csee::config::parse::<Cli>();
+    let output = cli.output.clone().unwrap_or_else(|| PathBuf::from(format!("csee-export.{}", cli.format.extension())));
+
+    let (courses, entries) = bundle(&cli.input, &cli.courses, &cli.theme)?;
+    match cli.format {
+        Format::Zip => write_zip(&output, &entries)?,
+        Format::TarZst => write_tar_zst(&output, &entries)?,
+    }
+    println!("Exported {} courses ({} files) to {}", courses.len(), entries.len(), output.display());
+    Ok(())
+}
+
+/// Reads the selected courses' pages, their attachments and the shared assets from `input`, and adds a course list of
+/// only those courses, `manifest.json` and `SHA256SUMS`. Keyed by path in the bundle.
+fn bundle(input: &Path, ids: &[String], theme_options: &ThemeOptions) -> io::Result<(Vec<Course>, Entries)> {
+    let courses = selected_courses(input, ids)?;
+    if courses.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No archived courses matched the selection"));
+    }
+
+    // Bundle paths are always '/'-separated, sorted so the manifest and archive are reproducible
+    let mut entries: Entries = BTreeMap::new();
+    for entry in fs::read_dir(input)? {
+        let path = entry?.path();
+        let is_asset = path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ASSET_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+        if path.is_file() && is_asset {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            entries.insert(name, fs::read(&path)?);
+        }
+    }
+
+    let mut manifest_courses = Vec::new();
+    for course in &courses {
+        let id = course.id.clone().unwrap_or_default();
+        let page = format!("courses/{}.html", id);
+        entries.insert(page.clone(), fs::read(input.join(&page))?);
+        let attachments = input.join("attachments").join(&id);
+        if attachments.is_dir() {
+            add_dir(&mut entries, input, &attachments)?;
+        }
+        manifest_courses.push(ManifestCourse {
+            id,
+            name: course.name.clone().unwrap_or_default(),
+            page,
+        });
+    }
+
+    // Course pages link back to ../courses.html, so the bundle carries its own list of only the exported courses
+    let theme = theme_options.load()?;
+    let tera = theme.templates().map_err(|e| io::Error::other(format!("Failed to load the templates: {}", e)))?;
+    // Older archives have no stats file, their index just leaves the counts out
+    let stats: HashMap<String, CourseStats> = match fs::read(input.join(STATS_FILE)) {
+        Ok(stats_json) => serde_json::from_slice(&stats_json)?,
+        Err(_) => HashMap::new(),
+    };
+    let mut context = index_context(&courses, &stats);
+    theme.insert(&mut context, "");
+    let course_list = tera.render("courses.html", &context)
+        .map_err(|e| io::Error::other(format!("Failed to render course list: {}", e)))?;
+    entries.insert("courses.html".to_string(), course_list.into_bytes());
+    entries.insert("index.html".to_string(), INDEX_HTML.as_bytes().to_vec());
+
+    let files: Vec<ManifestFile> = entries.iter().map(|(path, data)| ManifestFile {
+        path: path.clone(),
+        size: data.len(),
+        sha256: sha256_hex(data),
+    }).collect();
+    let sums: String = files.iter().map(|file| format!("{}  {}\n", file.sha256, file.path)).collect();
+    let manifest = Manifest {
+        generated_at: chrono::Utc::now().to_rfc3339(),
+        courses: manifest_courses,
+        files,
+    };
+    entries.insert("manifest.json".to_string(), serde_json::to_vec_pretty(&manifest)?);
+    entries.insert("SHA256SUMS".to_string(), sums.into_bytes());
+
+    Ok((courses, entries))
+}
+
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html lang="">
+  <head>
+    <meta charset="utf-8">
+    <title>Made by CSEE</title>
+    <meta http-equiv="refresh" content="0; url=courses.html">
+  </head>
+  <body>
+    <a href="courses.html">Open the course list</a>
+  </body>
+</html>
+"#;
+
+/// Reads `courses.json` from the archive and keeps the requested courses, or every course that has a page.
+fn selected_courses(input: &Path, ids: &[String]) -> io::Result<Vec<Course>> {
+    let course_json = fs::read(input.join("courses.json"))?;
+    let courses: Vec<Course> = serde_json::from_slice(&course_json)?;
+    for id in ids {
+        if !courses.iter().any(|course| course.id.as_ref() == Some(id)) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Course {} is not in the archive", id)));
+        }
+    }
+    Ok(courses.into_iter().filter(|course| {
+        let id = course.id.clone().unwrap_or_default();
+        let wanted = ids.is_empty() || ids.contains(&id);
+        wanted && input.join(format!("courses/{}.html", id)).is_file()
+    }).collect())
+}
+
+fn add_dir(entries: &mut Entries, root: &Path, dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            add_dir(entries, root, &path)?;
+        } else {
+            let relative = path.strip_prefix(root).unwrap();
+            let name = relative.components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            entries.insert(name, fs::read(&path)?);
+        }
+    }
+    Ok(())
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn write_zip(output: &Path, entries: &Entries) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new(File::create(output)?);
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (path, data) in entries {
+        zip.start_file(path.as_str(), options).map_err(io::Error::other)?;
+        zip.write_all(data)?;
+    }
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+fn write_tar_zst(output: &Path, entries: &Entries) -> io::Result<()> {
+    let encoder = zstd::Encoder::new(File::create(output)?, 0)?;
+    let mut tar = tar::Builder::new(encoder);
+    let mtime = chrono::Utc::now().timestamp() as u64;
+    for (path, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        tar.append_data(&mut header, path, data.as_slice())?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn bundles_the_selected_courses() {
+        let input = std::env::temp_dir().join(format!("csee-export-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&input);
+        fs::create_dir_all(input.join("courses")).unwrap();
+        fs::create_dir_all(input.join("attachments/1")).unwrap();
+        let courses = json!([{"id": "1", "name": "Biology"}, {"id": "2", "name": "Chemistry"}]);
+        fs::write(input.join("courses.json"), courses.to_string()).unwrap();
+        fs::write(input.join("courses/1.html"), "<p>Biology</p>").unwrap();
+        fs::write(input.join("courses/2.html"), "<p>Chemistry</p>").unwrap();
+        fs::write(input.join("attachments/1/notes.txt"), "cells").unwrap();
+        fs::write(input.join("styles.css"), "body {}").unwrap();
+
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let theme = ThemeOptions { theme: manifest_dir.join("themes/boscotech"), templates: manifest_dir.join("templates"), ..Default::default() };
+        let (courses, entries) = bundle(&input, &["1".to_string()], &theme).unwrap();
+        assert_eq!(courses.len(), 1);
+        let paths: Vec<&str> = entries.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["SHA256SUMS", "attachments/1/notes.txt", "courses.html", "courses/1.html", "index.html", "manifest.json", "styles.css"]);
+        let course_list = String::from_utf8_lossy(&entries["courses.html"]);
+        assert!(course_list.contains("Biology") && !course_list.contains("Chemistry"));
+
+        let manifest: Value = serde_json::from_slice(&entries["manifest.json"]).unwrap();
+        assert_eq!(manifest["courses"], json!([{"id": "1", "name": "Biology", "page": "courses/1.html"}]));
+        // Every other file is listed, with the checksum SHA256SUMS gives it
+        let sums = String::from_utf8_lossy(&entries["SHA256SUMS"]).to_string();
+        let files = manifest["files"].as_array().unwrap();
+        assert_eq!(files.len(), entries.len() - 2);
+        for file in files {
+            let path = file["path"].as_str().unwrap();
+            assert_eq!(file["sha256"].as_str().unwrap(), sha256_hex(&entries[path]));
+            assert_eq!(file["size"].as_u64().unwrap() as usize, entries[path].len());
+            assert!(sums.contains(&format!("{}  {}\n", sha256_hex(&entries[path]), path)), "{}", path);
+        }
+
+        let zip_path = input.join("bundle.zip");
+        write_zip(&zip_path, &entries).unwrap();
+        let mut zip = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let mut page = String::new();
+        io::Read::read_to_string(&mut zip.by_name("courses/1.html").unwrap(), &mut page).unwrap();
+        assert_eq!((zip.len(), page.as_str()), (entries.len(), "<p>Biology</p>"));
+
+        assert!(bundle(&input, &["3".to_string()], &theme).is_err());
+        fs::remove_dir_all(&input).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+use crate::api::Api;
+use crate::guardians::{fetch_guardians, StudentGuardians};
+use crate::metrics::{Metrics, METRICS};
+use crate::questions::{tally_answers, Tally};
+use crate::roster::{apply_privacy, list_students, StudentPrivacy};
+use crate::scopes::{Feature, Features};
+use classroom1::api::{Announcement, CourseWork, CourseWorkMaterial, Student, Teacher, Topic};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+/// How long one section of a course took to fetch, and whether it made it.
+#[derive(Clone, Debug)]
+pub struct SectionTiming {
+    pub name: &'static str,
+    pub elapsed: Duration,
+    pub failed: bool,
+}
+
+/// Per-section timings, printed as "announcements 120ms, coursework 340ms, topics 80ms (failed)".
+#[derive(Clone, Debug, Default)]
+pub struct Timings(pub Vec<SectionTiming>);
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, section) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} {:?}", section.name, section.elapsed)?;
+            if section.failed {
+                f.write_str(" (failed)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything a course page is rendered from.
+///
+/// A section that failed is left empty and named in `failed`, so one bad endpoint costs that section rather than the course.
+///
+/// Class comments and private comments are not here: Classroom API v1 has no endpoint that returns them, for
+/// announcements or coursework, so they can't be archived. Redaction already drops a `privateComments` field
+/// should one ever show up in the data.
+#[derive(Clone, Debug, Default)]
+pub struct CourseData {
+    pub announcements: Vec<Announcement>,
+    pub coursework: Vec<CourseWork>,
+    pub materials: Vec<CourseWorkMaterial>,
+    pub teachers: Vec<Teacher>,
+    pub topics: Vec<Topic>,
+    /// `None` when the roster isn't archived
+    pub students: Option<Vec<Student>>,
+    /// Answer tallies by coursework ID, empty unless `fetch_answers` was called
+    pub answers: HashMap<String, Tally>,
+    /// Each student's guardians, empty unless `fetch_guardians` was called
+    pub guardians: Vec<StudentGuardians>,
+    pub failed: Vec<&'static str>,
+    pub timings: Timings,
+}
+
+impl CourseData {
+    fn record<T: Default>(&mut self, (name, elapsed, result): (&'static str, Duration, classroom1::Result<T>)) -> T {
+        self.timings.0.push(SectionTiming { name, elapsed, failed: result.is_err() });
+        result.unwrap_or_else(|error| {
+            tracing::warn!(section = name, error = %error, "Failed to fetch section");
+            Metrics::add(&METRICS.section_failures, 1);
+            self.failed.push(name);
+            T::default()
+        })
+    }
+
+    /// Tallies the answers to every question in the coursework, as one more section.
+    ///
+    /// Only worth calling for courses the account teaches, since a student is only sent their own submission.
+    pub async fn fetch_answers(&mut self, api: &Api, id: &str) {
+        let answers = timed("answers", tally_answers(api, id, &self.coursework)).await;
+        self.answers = self.record(answers);
+    }
+
+    /// Fetches each student's guardians and pending invitations, as one more section. Students are shown as
+    /// `students` shows them, and nothing is fetched when the roster is omitted.
+    ///
+    /// Only a teacher of the course can read these.
+    pub async fn fetch_guardians(&mut self, api: &Api, id: &str, students: StudentPrivacy) {
+        let guardians = timed("guardians", fetch_guardians(api, id, students)).await;
+        self.guardians = self.record(guardians);
+    }
+}
+
+async fn timed<T>(name: &'static str, fetch: impl Future<Output = classroom1::Result<T>>) -> (&'static str, Duration, classroom1::Result<T>) {
+    let start_time = Instant::now();
+    let result = fetch.instrument(tracing::info_span!("section", name)).await;
+    (name, start_time.elapsed(), result)
+}
+
+/// Fetches every section of a course at once. The requests still queue behind the `Api` limits.
+///
+/// Teachers and students are only fetched with the roster feature, and teachers' photos are dropped without the
+/// photos feature.
+pub async fn fetch_course(api: &Api, id: &str, students: StudentPrivacy, features: &Features) -> CourseData {
+    let roster_enabled = features.has(Feature::Roster);
+    let students = if roster_enabled { students } else { StudentPrivacy::Omit };
+    let teachers = async {
+        if roster_enabled {
+            api.call(|| api.hub().courses().teachers_list(id).doit()).await.map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+    let roster = async {
+        match students {
+            StudentPrivacy::Omit => Ok(None),
+            privacy => Ok(Some(apply_privacy(list_students(api, id).await?, privacy))),
+        }
+    };
+    let (announcements, coursework, materials, teachers, topics, roster) = tokio::join!(
+        timed("announcements", api.call(|| api.hub().courses().announcements_list(id).doit())),
+        timed("coursework", api.call(|| api.hub().courses().course_work_list(id).doit())),
+        timed("materials", api.call(|| api.hub().courses().course_work_materials_list(id).doit())),
+        timed("teachers", teachers),
+        timed("topics", api.call(|| api.hub().courses().topics_list(id).doit())),
+        timed("students", roster),
+    );
+
+    let mut data = CourseData::default();
+    let announcements = data.record(announcements).announcements.unwrap_or_default();
+    let coursework = data.record(coursework).course_work.unwrap_or_default();
+    let materials = data.record(materials).course_work_material.unwrap_or_default();
+    let mut teachers = if roster_enabled {
+        data.record(teachers).and_then(|response| response.teachers).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    if !features.has(Feature::Photos) {
+        for profile in teachers.iter_mut().filter_map(|teacher| teacher.profile.as_mut()) {
+            profile.photo_url = None;
+        }
+    }
+    let topics = data.record(topics).topic.unwrap_or_default();
+    let students = match students {
+        StudentPrivacy::Omit => None,
+        _ => data.record(roster),
+    };
+    CourseData { announcements, coursework, materials, teachers, topics, students, ..data }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use serde_json::json;
+
+    async fn server() -> MockServer {
+        let server = MockServer::start().await;
+        server.route("/v1/courses/1/announcements", json!({"announcements": [{"id": "a", "text": "Welcome"}]}));
+        server.route("/v1/courses/1/courseWork", json!({"courseWork": [{"id": "w", "title": "Essay"}]}));
+        server.route("/v1/courses/1/courseWorkMaterials", json!({"courseWorkMaterial": []}));
+        server.route("/v1/courses/1/teachers", json!({"teachers": [{"userId": "111"}]}));
+        server.route("/v1/courses/1/students", json!({"students": [{"userId": "222"}]}));
+        server
+    }
+
+    #[tokio::test]
+    async fn a_failed_section_leaves_the_rest() {
+        // No route for topics, so it answers 404
+        let server = server().await;
+        let api = server.api();
+
+        let data = fetch_course(&api, "1", StudentPrivacy::Full, &Features::default()).await;
+        assert_eq!(data.failed, vec!["topics"]);
+        assert_eq!(data.announcements.len(), 1);
+        assert_eq!(data.coursework.len(), 1);
+        assert_eq!(data.teachers.len(), 1);
+        assert_eq!(data.students.map(|students| students.len()), Some(1));
+        assert_eq!(data.timings.0.len(), 6);
+        assert!(data.timings.to_string().contains("topics"));
+    }
+
+    #[tokio::test]
+    async fn omitted_students_are_not_fetched() {
+        let server = server().await;
+        server.route("/v1/courses/1/topics", json!({"topic": []}));
+        let api = server.api();
+
+        let data = fetch_course(&api, "1", StudentPrivacy::Omit, &Features::default()).await;
+        assert!(data.failed.is_empty());
+        assert!(data.students.is_none());
+        assert_eq!(server.requests(), 5);
+    }
+
+    #[tokio::test]
+    async fn the_roster_is_not_fetched_without_its_feature() {
+        let server = server().await;
+        server.route("/v1/courses/1/topics", json!({"topic": []}));
+        let api = server.api();
+
+        let data = fetch_course(&api, "1", StudentPrivacy::Full, &Features::new([])).await;
+        assert!(data.failed.is_empty());
+        assert!(data.teachers.is_empty());
+        assert!(data.students.is_none());
+        assert_eq!(server.requests(), 4);
+        assert!(data.timings.0.iter().all(|section| section.name != "teachers"), "{}", data.timings);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::api::Api;
+use crate::term::{Term, TermSelector};
+use classroom1::api::Course;
+use regex::Regex;
+
+/// Whose courses to list: the ones the authenticated user teaches or the ones they take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Role {
+    Teacher,
+    Student,
+}
+
+/// Narrows down which courses get archived.
+///
+/// States and role are sent to `courses.list`, everything else is checked against each returned course.
+/// An empty filter matches every course, which is what the archiver did before filters existed.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct CourseFilter {
+    /// Only keep courses in this state (ACTIVE, ARCHIVED, PROVISIONED, DECLINED, SUSPENDED). Repeatable.
+    #[arg(long = "state")]
+    pub states: Vec<String>,
+    /// Only keep courses the authenticated user teaches or takes
+    #[arg(long, value_enum)]
+    pub role: Option<Role>,
+    /// Regex matched against the course name
+    #[arg(long)]
+    pub name: Option<Regex>,
+    /// Regex matched against the course section
+    #[arg(long)]
+    pub section: Option<Regex>,
+    /// Only keep this course ID. Repeatable.
+    #[arg(long = "course")]
+    pub ids: Vec<String>,
+    /// A semester ("Fall 2022", "Sem 2 2022-23") or an academic year ("2022-23")
+    #[arg(long, value_parser = parse_term)]
+    pub term: Option<TermSelector>,
+}
+
+impl CourseFilter {
+    pub fn matches(&self, course: &Course) -> bool {
+        if !self.states.is_empty() {
+            let state = course.course_state.as_deref().unwrap_or_default();
+            if !self.states.iter().any(|wanted| wanted.eq_ignore_ascii_case(state)) {
+                return false;
+            }
+        }
+        if !self.ids.is_empty() && !course.id.as_ref().is_some_and(|id| self.ids.contains(id)) {
+            return false;
+        }
+        if let Some(name) = &self.name {
+            if !name.is_match(course.name.as_deref().unwrap_or_default()) {
+                return false;
+            }
+        }
+        if let Some(section) = &self.section {
+            if !section.is_match(course.section.as_deref().unwrap_or_default()) {
+                return false;
+            }
+        }
+        if let Some(term) = &self.term {
+            if !term.matches(Term::of_course(course)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn parse_term(text: &str) -> Result<TermSelector, String> {
+    TermSelector::parse(text).ok_or_else(|| format!("'{}' is not a term like \"Fall 2022\" or \"2022-23\"", text))
+}
+
+/// Lists every page of the user's courses and keeps the ones the filter matches.
+pub async fn list_courses(api: &Api, filter: &CourseFilter) -> classroom1::Result<Vec<Course>> {
+    let mut courses = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let response = api.call(|| {
+            let mut call = api.hub().courses().list().page_size(api.page_size());
+            for state in &filter.states {
+                call = call.add_course_states(&state.to_uppercase());
+            }
+            call = match filter.role {
+                Some(Role::Teacher) => call.teacher_id("me"),
+                Some(Role::Student) => call.student_id("me"),
+                None => call,
+            };
+            if let Some(token) = &page_token {
+                call = call.page_token(token);
+            }
+            call.doit()
+        }).await?;
+        courses.extend(response.courses.unwrap_or_default().into_iter().filter(|course| filter.matches(course)));
+        page_token = response.next_page_token.filter(|token| !token.is_empty());
+        if page_token.is_none() {
+            return Ok(courses);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn course() -> Course {
+        Course {
+            id: Some("42".to_string()),
+            name: Some("Biology 101".to_string()),
+            section: Some("Period 2, Fall 2022".to_string()),
+            course_state: Some("ACTIVE".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn every_field_narrows_the_match() {
+        let course = course();
+        assert!(CourseFilter::default().matches(&course));
+
+        let states = |states: &[&str]| CourseFilter { states: states.iter().map(|state| state.to_string()).collect(), ..Default::default() };
+        assert!(states(&["archived", "active"]).matches(&course));
+        assert!(!states(&["ARCHIVED"]).matches(&course));
+
+        let ids = |ids: &[&str]| CourseFilter { ids: ids.iter().map(|id| id.to_string()).collect(), ..Default::default() };
+        assert!(ids(&["7", "42"]).matches(&course));
+        assert!(!ids(&["7"]).matches(&course));
+
+        let name = |pattern: &str| CourseFilter { name: Some(Regex::new(pattern).unwrap()), ..Default::default() };
+        assert!(name("^Bio").matches(&course));
+        assert!(!name("Chem").matches(&course));
+
+        let section = |pattern: &str| CourseFilter { section: Some(Regex::new(pattern).unwrap()), ..Default::default() };
+        assert!(section("Period [12]").matches(&course));
+        assert!(!section("Period 3").matches(&course));
+
+        let term = |text: &str| CourseFilter { term: Some(parse_term(text).unwrap()), ..Default::default() };
+        assert!(term("Sem 1 2022-23").matches(&course));
+        assert!(term("2022-23").matches(&course));
+        assert!(!term("Spring 2023").matches(&course));
+        assert!(parse_term("someday").is_err());
+
+        // Every field has to match
+        assert!(!CourseFilter { name: Some(Regex::new("^Bio").unwrap()), ..states(&["ARCHIVED"]) }.matches(&course));
+    }
+}
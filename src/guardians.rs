@@ -0,0 +1,126 @@
+use crate::api::Api;
+use crate::roster::{list_students, with_privacy, StudentPrivacy};
+use classroom1::api::{Guardian, GuardianInvitation, Scope, Student};
+use futures::future::try_join_all;
+use serde::Serialize;
+
+/// One student's guardians, and the invitations sent to guardians who haven't accepted yet.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StudentGuardians {
+    /// As the roster shows the student, so a pseudonymised roster stays pseudonymised here
+    pub student: Student,
+    pub guardians: Vec<Guardian>,
+    pub invitations: Vec<GuardianInvitation>,
+}
+
+/// Lists every page of a student's guardians.
+pub async fn list_guardians(api: &Api, student_id: &str) -> classroom1::Result<Vec<Guardian>> {
+    let mut guardians = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let response = api.call(|| {
+            // The call defaults to the scope for the signed-in user's own guardians
+            let mut call = api.hub().user_profiles().guardians_list(student_id)
+                .page_size(api.page_size())
+                .add_scope(Scope::GuardianlinkStudentReadonly);
+            if let Some(token) = &page_token {
+                call = call.page_token(token);
+            }
+            call.doit()
+        }).await?;
+        guardians.extend(response.guardians.unwrap_or_default());
+        page_token = response.next_page_token.filter(|token| !token.is_empty());
+        if page_token.is_none() {
+            return Ok(guardians);
+        }
+    }
+}
+
+/// Lists every page of a student's pending guardian invitations. Accepted ones show up as guardians instead.
+pub async fn list_invitations(api: &Api, student_id: &str) -> classroom1::Result<Vec<GuardianInvitation>> {
+    let mut invitations = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let response = api.call(|| {
+            let mut call = api.hub().user_profiles().guardian_invitations_list(student_id)
+                .page_size(api.page_size())
+                .add_states("PENDING");
+            if let Some(token) = &page_token {
+                call = call.page_token(token);
+            }
+            call.doit()
+        }).await?;
+        invitations.extend(response.guardian_invitations.unwrap_or_default());
+        page_token = response.next_page_token.filter(|token| !token.is_empty());
+        if page_token.is_none() {
+            return Ok(invitations);
+        }
+    }
+}
+
+/// Guardians and pending invitations of every student in a course, in roster order.
+///
+/// Nothing is fetched when the roster is omitted. Only a teacher of the course can read these.
+pub async fn fetch_guardians(api: &Api, course_id: &str, privacy: StudentPrivacy) -> classroom1::Result<Vec<StudentGuardians>> {
+    if privacy == StudentPrivacy::Omit {
+        return Ok(Vec::new());
+    }
+    let students = with_privacy(list_students(api, course_id).await?, privacy);
+    try_join_all(students.into_iter().filter_map(|(user_id, student)| Some((user_id?, student))).map(|(user_id, student)| async move {
+        let (guardians, invitations) = tokio::try_join!(list_guardians(api, &user_id), list_invitations(api, &user_id))?;
+        Ok(StudentGuardians { student, guardians, invitations })
+    })).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use serde_json::json;
+
+    async fn server() -> MockServer {
+        let server = MockServer::start().await;
+        server.route("/v1/courses/1/students", json!({"students": [
+            {"userId": "2", "profile": {"name": {"fullName": "Bea"}}},
+            {"userId": "1", "profile": {"name": {"fullName": "Al"}}},
+        ]}));
+        server.route("/v1/userProfiles/1/guardians", json!({"guardians": [
+            {"studentId": "1", "guardianId": "9", "guardianProfile": {"name": {"fullName": "Parent"}}},
+        ]}));
+        server.route("/v1/userProfiles/1/guardianInvitations", json!({}));
+        server.route("/v1/userProfiles/2/guardians", json!({}));
+        server.route("/v1/userProfiles/2/guardianInvitations", json!({"guardianInvitations": [
+            {"studentId": "2", "invitedEmailAddress": "carer@example.com", "state": "PENDING"},
+        ]}));
+        server
+    }
+
+    fn api(server: &MockServer) -> Api {
+        server.api()
+    }
+
+    #[tokio::test]
+    async fn guardians_follow_the_roster() {
+        let server = server().await;
+        let guardians = fetch_guardians(&api(&server), "1", StudentPrivacy::Full).await.unwrap();
+        let names: Vec<_> = guardians.iter().map(|entry| entry.student.profile.as_ref().unwrap().name.as_ref().unwrap().full_name.clone().unwrap()).collect();
+        assert_eq!(names, ["Al", "Bea"]);
+        assert_eq!(guardians[0].guardians[0].guardian_id.as_deref(), Some("9"));
+        assert!(guardians[0].invitations.is_empty());
+        assert_eq!(guardians[1].invitations[0].invited_email_address.as_deref(), Some("carer@example.com"));
+
+        // Pseudonymised students still get their own guardians, without their name or ID
+        let guardians = fetch_guardians(&api(&server), "1", StudentPrivacy::Pseudonymise).await.unwrap();
+        let first = serde_json::to_value(&guardians[0].student).unwrap();
+        assert_eq!(first["profile"]["name"]["fullName"], "Student 1");
+        assert!(first["userId"].is_null());
+        assert_eq!(guardians[0].guardians.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn an_omitted_roster_fetches_nothing() {
+        let server = server().await;
+        assert!(fetch_guardians(&api(&server), "1", StudentPrivacy::Omit).await.unwrap().is_empty());
+        assert_eq!(server.requests(), 0);
+    }
+}
@@ -0,0 +1,339 @@
+//! Revision history of announcements and coursework, so edits made after posting aren't lost.
+//!
+//! Each run adds a revision for every item whose `updateTime` is new and whose title, description, due date or points
+//! differ from the last revision kept. Histories are kept per page under `history/` in the output directory, already
+//! redacted, since they sit next to the pages.
+
+use crate::fetch::CourseData;
+use crate::redact::{Roles, Rules};
+use classroom1::api::{Announcement, CourseWork};
+use classroom1::chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory in the output the histories are kept in
+pub const HISTORY_DIR: &str = "history";
+
+/// Past this many token pairs a description's diff is shown as all removed and all added, rather than computed.
+const MAX_DIFF_CELLS: usize = 1_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemKind {
+    Announcement,
+    Coursework,
+}
+
+/// The fields of an item whose edits are kept.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Revision {
+    pub update_time: DateTime<Utc>,
+    pub title: Option<String>,
+    /// An announcement's text or a coursework's description
+    pub description: Option<String>,
+    /// Midnight UTC when the coursework is due on a day with no time
+    pub due: Option<DateTime<Utc>>,
+    pub points: Option<f64>,
+}
+
+impl Revision {
+    fn of_announcement(announcement: &Announcement) -> Option<Revision> {
+        Some(Revision {
+            update_time: announcement.update_time?,
+            description: announcement.text.clone(),
+            ..Default::default()
+        })
+    }
+
+    fn of_coursework(work: &CourseWork) -> Option<Revision> {
+        let due = work.due_date.as_ref().and_then(|date| {
+            let date = NaiveDate::from_ymd_opt(date.year?, date.month? as u32, date.day? as u32)?;
+            let time = work.due_time.as_ref();
+            let at = |value: Option<i32>| value.unwrap_or(0) as u32;
+            let time = date.and_hms_opt(at(time.and_then(|t| t.hours)), at(time.and_then(|t| t.minutes)), at(time.and_then(|t| t.seconds)))?;
+            Some(time.and_utc())
+        });
+        Some(Revision {
+            update_time: work.update_time?,
+            title: work.title.clone(),
+            description: work.description.clone(),
+            due,
+            points: work.max_points,
+        })
+    }
+
+    fn same_content(&self, other: &Revision) -> bool {
+        Revision { update_time: other.update_time, ..self.clone() } == *other
+    }
+}
+
+/// One item's revisions, oldest first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ItemHistory {
+    pub kind: ItemKind,
+    pub revisions: Vec<Revision>,
+}
+
+/// Every item's history on one course page, by item ID.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CourseHistory {
+    items: BTreeMap<String, ItemHistory>,
+}
+
+impl CourseHistory {
+    fn path(output: &Path, page_id: &str) -> PathBuf {
+        output.join(HISTORY_DIR).join(format!("{}.json", page_id))
+    }
+
+    /// The history kept for a course page, empty when there is none yet.
+    pub fn load(output: &Path, page_id: &str) -> CourseHistory {
+        fs::read(CourseHistory::path(output, page_id)).ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, output: &Path, page_id: &str) -> io::Result<()> {
+        fs::create_dir_all(output.join(HISTORY_DIR))?;
+        fs::write(CourseHistory::path(output, page_id), serde_json::to_vec(self)?)
+    }
+
+    /// Adds the revisions in `data` that aren't kept yet, redacted by `rules`. Returns how many were added.
+    pub fn record(&mut self, data: &CourseData, rules: &Rules) -> usize {
+        let announcements = data.announcements.iter().filter_map(|item| Some((item.id.clone()?, ItemKind::Announcement, Revision::of_announcement(item)?)));
+        let coursework = data.coursework.iter().filter_map(|item| Some((item.id.clone()?, ItemKind::Coursework, Revision::of_coursework(item)?)));
+        let mut added = 0;
+        for (id, kind, revision) in announcements.chain(coursework) {
+            let revision = redacted(revision, rules);
+            let history = self.items.entry(id).or_insert_with(|| ItemHistory { kind, revisions: Vec::new() });
+            // Edits to fields that aren't kept, e.g. attachments, bump `updateTime` too
+            let kept = history.revisions.last().is_some_and(|last| last.update_time >= revision.update_time || last.same_content(&revision));
+            if !kept {
+                history.revisions.push(revision);
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Whether the item has been edited since it was first archived.
+    pub fn has_edits(&self, id: &str) -> bool {
+        self.items.get(id).is_some_and(|history| history.revisions.len() > 1)
+    }
+
+    /// The edited items, each with its versions newest first, for history.html.
+    pub fn pages(&self) -> Vec<ItemPage> {
+        self.items.iter().filter(|(id, _)| self.has_edits(id)).map(|(id, history)| {
+            let latest = history.revisions.last().expect("edited items have revisions");
+            let mut versions: Vec<Version> = history.revisions.windows(2).map(|pair| Version {
+                update_time: pair[1].update_time,
+                changes: changes(&pair[0], &pair[1]),
+            }).collect();
+            versions.reverse();
+            ItemPage {
+                id: id.clone(),
+                kind: history.kind,
+                title: latest.title.clone(),
+                first: history.revisions[0].clone(),
+                versions,
+            }
+        }).collect()
+    }
+}
+
+fn redacted(revision: Revision, rules: &Rules) -> Revision {
+    if rules.is_noop() {
+        return revision;
+    }
+    let mut value = serde_json::to_value(&revision).expect("a revision serialises");
+    rules.redact_value(&mut value, &Roles::default());
+    serde_json::from_value(value).unwrap_or(revision)
+}
+
+/// An edited item on history.html.
+#[derive(Clone, Debug, Serialize)]
+pub struct ItemPage {
+    /// Also the item's anchor on the page
+    pub id: String,
+    pub kind: ItemKind,
+    pub title: Option<String>,
+    /// The item as first archived
+    pub first: Revision,
+    /// Every later version, newest first
+    pub versions: Vec<Version>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Version {
+    pub update_time: DateTime<Utc>,
+    pub changes: Vec<FieldChange>,
+}
+
+/// A field that changed between two versions.
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldChange {
+    /// `title`, `description`, `due` or `points`
+    pub field: &'static str,
+    pub old: Value,
+    pub new: Value,
+    /// The text fields, word by word
+    pub words: Vec<Word>,
+}
+
+/// A run of text that was kept, added or removed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Word {
+    /// `same`, `added` or `removed`
+    pub change: &'static str,
+    pub text: String,
+}
+
+fn changes(old: &Revision, new: &Revision) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    let text = |field, old: &Option<String>, new: &Option<String>| (old != new).then(|| FieldChange {
+        field,
+        old: old.clone().into(),
+        new: new.clone().into(),
+        words: diff_words(old.as_deref().unwrap_or_default(), new.as_deref().unwrap_or_default()),
+    });
+    changes.extend(text("title", &old.title, &new.title));
+    changes.extend(text("description", &old.description, &new.description));
+    if old.due != new.due {
+        changes.push(FieldChange { field: "due", old: serde_json::to_value(old.due).unwrap(), new: serde_json::to_value(new.due).unwrap(), words: Vec::new() });
+    }
+    if old.points != new.points {
+        changes.push(FieldChange { field: "points", old: old.points.into(), new: new.points.into(), words: Vec::new() });
+    }
+    changes
+}
+
+/// Splits text into runs of whitespace and of everything else.
+fn tokens(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        match chars.peek() {
+            Some(&(end, next)) if next.is_whitespace() != c.is_whitespace() => {
+                tokens.push(&text[start..end]);
+                start = end;
+            }
+            Some(_) => (),
+            None => tokens.push(&text[start..]),
+        }
+    }
+    tokens
+}
+
+/// Diffs two texts a word at a time, removals before additions. Neighbouring words with the same change are joined.
+pub fn diff_words(old: &str, new: &str) -> Vec<Word> {
+    let old = tokens(old);
+    let new = tokens(new);
+    let mut words: Vec<Word> = Vec::new();
+    let mut push = |change: &'static str, text: &str| match words.last_mut() {
+        Some(last) if last.change == change => last.text.push_str(text),
+        _ => words.push(Word { change, text: text.to_string() }),
+    };
+    if old.len() * new.len() > MAX_DIFF_CELLS {
+        old.iter().for_each(|word| push("removed", word));
+        new.iter().for_each(|word| push("added", word));
+        return words;
+    }
+    // Longest common subsequence of the suffixes, so the walk below can go front to back
+    let width = new.len() + 1;
+    let mut common = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i * width + j] = if old[i] == new[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            push("same", old[i]);
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[(i + 1) * width + j] >= common[i * width + j + 1]) {
+            push("removed", old[i]);
+            i += 1;
+        } else {
+            push("added", new[j]);
+            j += 1;
+        }
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redact::FieldAction;
+    use crate::theme::tests::bundled;
+    use classroom1::api::Date;
+    use classroom1::chrono::TimeZone;
+
+    fn work(day: u32, description: &str, points: f64) -> CourseWork {
+        CourseWork {
+            id: Some("w".to_string()),
+            title: Some("Essay".to_string()),
+            description: Some(description.to_string()),
+            update_time: Some(Utc.with_ymd_and_hms(2024, 3, day, 9, 0, 0).unwrap()),
+            due_date: Some(Date { year: Some(2024), month: Some(3), day: Some(20) }),
+            max_points: Some(points),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn words_are_diffed() {
+        let words = diff_words("Write 500 words on Hamlet.", "Write 800 words on Hamlet or Macbeth.");
+        let text = |change| words.iter().filter(|word| word.change == change).map(|word| word.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(text("removed"), ["500", "Hamlet."]);
+        assert_eq!(text("added"), ["800", "Hamlet or Macbeth."]);
+        assert_eq!(words[0], Word { change: "same", text: "Write ".to_string() });
+        assert!(diff_words("", "").is_empty());
+        assert_eq!(diff_words("a  b", "a  b c")[1], Word { change: "added", text: " c".to_string() });
+    }
+
+    #[test]
+    fn edits_become_revisions() {
+        let output = std::env::temp_dir().join(format!("csee-history-{}", std::process::id()));
+        let rules = Rules { emails: FieldAction::Strip, ..Default::default() };
+        let mut history = CourseHistory::load(&output, "1");
+        let data = |work| CourseData { coursework: vec![work], ..Default::default() };
+        assert_eq!(history.record(&data(work(1, "Email me at teacher@example.com", 10.0)), &rules), 1);
+        // Seen already, and a newer updateTime with nothing kept changed
+        assert_eq!(history.record(&data(work(1, "Email me at teacher@example.com", 10.0)), &rules), 0);
+        assert_eq!(history.record(&data(work(2, "Email me at teacher@example.com", 10.0)), &rules), 0);
+        assert!(!history.has_edits("w"));
+        assert_eq!(history.record(&data(work(3, "Email me at teacher@example.com please", 20.0)), &rules), 1);
+        history.save(&output, "1").unwrap();
+
+        let history = CourseHistory::load(&output, "1");
+        assert!(history.has_edits("w"));
+        let pages = history.pages();
+        let changes = &pages[0].versions[0].changes;
+        let fields: Vec<_> = changes.iter().map(|change| change.field).collect();
+        assert_eq!(fields, ["description", "points"]);
+        assert!(!serde_json::to_string(&pages).unwrap().contains("teacher@example.com"));
+        assert_eq!(pages[0].first.due, Some(Utc.with_ymd_and_hms(2024, 3, 20, 0, 0, 0).unwrap()));
+        fs::remove_dir_all(&output).unwrap();
+
+        let theme = bundled();
+        let mut context = tera::Context::new();
+        context.insert("name", "English");
+        context.insert("items", &pages);
+        context.insert("course_page", "1.html");
+        theme.insert(&mut context, "../");
+        let html = theme.templates().unwrap().render("history.html", &context).unwrap();
+        assert!(html.contains("[email removed]<ins> please</ins>"), "{}", html);
+        assert!(html.contains("<del>10.0</del>") || html.contains("<del>10</del>"), "{}", html);
+    }
+}
@@ -1,228 +1,266 @@
 extern crate google_classroom1 as classroom1;
-use classroom1::api::ListCoursesResponse;
-use classroom1::hyper_rustls::HttpsConnector;
-use classroom1::{Classroom, hyper, hyper_rustls};
-use hyper::Body;
-use hyper::Response;
-use hyper::client::HttpConnector;
-use oauth2::Scope;
-use serde_json::Value;
-use tera::Tera;
-use tera::Context;
+use classroom1::api::Course;
+use classroom1::chrono::{DateTime, FixedOffset};
+use clap::Parser;
+use csee::api::Limits;
+use csee::archive::{assigned_courses, connect, invitations, page_groups, templates, Account, Error, Site};
+use csee::cache::CacheOptions;
+use csee::config::Config;
+use csee::daemon::{course_schedule, load_fingerprints, now_in, save_fingerprints, Fingerprint, RunLock, Schedule};
+use csee::embeds::EmbedOptions;
+use csee::filter::CourseFilter;
+use csee::index::{index_context, redact_index, CourseStats, STATS_FILE};
+use csee::invitations::INVITATIONS_PAGE;
+use csee::metrics::{Metrics, METRICS};
+use csee::redact::{Redaction, Roles};
+use csee::roster::StudentPrivacy;
+use csee::schedule::{Concurrency, Scheduler};
+use csee::scopes::Features;
+use csee::telemetry::Logging;
+use csee::theme::ThemeOptions;
+use std::path::PathBuf;
 use std::time::Instant;
-use std::{fs::File, io::Write, collections::HashMap};
-use std::sync::mpsc;
-use tokio::runtime::Runtime;
+use std::{fs::{self, File}, io::Write, collections::{HashMap, HashSet}};
 
-#[derive(Clone)]
-struct Args {
-    id: Option<String>,
-    name: Option<String>,
-    hub: Option<Classroom<HttpsConnector<HttpConnector>>>,
-    tera: Option<Tera>,
+/// Archive Google Classroom courses as static HTML under html/
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    filter: CourseFilter,
+    /// Whether to archive each course's student roster, and how much of it
+    #[arg(long, value_enum, default_value = "omit")]
+    students: StudentPrivacy,
+    /// TOML file with the privacy redaction rules applied before anything is rendered
+    #[arg(long)]
+    redaction: Option<PathBuf>,
+    /// Merge courses that share an alias, or a `merge` name in the configuration, into one page
+    #[arg(long)]
+    merge_sections: bool,
+    #[command(flatten)]
+    embeds: EmbedOptions,
+    /// Keep running, archiving on the schedules in the configuration's [daemon] section
+    #[arg(long)]
+    daemon: bool,
+    #[command(flatten)]
+    features: Features,
+    #[command(flatten)]
+    theme: ThemeOptions,
+    #[command(flatten)]
+    limits: Limits,
+    #[command(flatten)]
+    concurrency: Concurrency,
+    #[command(flatten)]
+    cache: CacheOptions,
+    #[command(flatten)]
+    logging: Logging,
 }
 
-#[tokio::main (flavor = "multi_thread", worker_threads = 100) ]
-async fn main() {
-    let secret = classroom1::oauth2::read_application_secret("credentials.json")
-        .await
-        .expect("client secret couldn't be read.");
-    let auth = classroom1::oauth2::InstalledFlowAuthenticator::builder(
-        secret,
-        classroom1::oauth2::InstalledFlowReturnMethod::HTTPRedirect,
-    )
-    .persist_tokens_to_disk("tokens.json")
-    .build()
-    .await
-    .expect("InstalledFlowAuthenticator failed to build");
-    let _scopes = vec![
-        Scope::new("https://www.googleapis.com/auth/drive.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.announcements.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.courses.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.coursework.students.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.coursework.me.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.courseworkmaterials.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.rosters.readonly".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.profile.emails".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.profile.photos".to_string()),
-        Scope::new("https://www.googleapis.com/auth/classroom.topics.readonly".to_string()),
-    ];
-    match auth.token(&_scopes).await {
-        Ok(_token) => (),
-        Err(e) => println!("error: {:?}", e),
+fn main() {
+    let (cli, config) = csee::config::parse::<Cli>();
+    cli.logging.init();
+    // Sized from the command line, so it can't come from `#[tokio::main]`
+    let runtime = cli.concurrency.runtime().expect("Failed to start the runtime");
+    if cli.daemon {
+        runtime.block_on(daemon(&cli, &config));
+        return;
     }
+    // A scheduled run may be writing the archive right now
+    let lock = RunLock::acquire(&config.lock_file()).unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    });
+    if let Err(error) = runtime.block_on(archive(&cli, &config, Run::Full)) {
+        eprintln!("error: {}", error);
+        // Exiting skips destructors, and the lock is left for nobody
+        drop(lock);
+        std::process::exit(1);
+    }
+}
 
-    let hub = Classroom::new(hyper::Client::builder().build(hyper_rustls::HttpsConnectorBuilder::new().with_native_roots().unwrap().https_or_http().enable_http1().build()), auth);
-    let courses = hub.courses();
-    let response: (Response<Body>, ListCoursesResponse) = courses.list().page_size(100).doit().await.unwrap();
-
-    let mut tera = Tera::new("../templates/**/*.html").unwrap();
-    let mut context = Context::new();
-    let mut buffer = Vec::new();
-    let course_list = response.1.courses.clone().unwrap();
-    tera.add_template_file("templates/courses.html", Some("course_list")).unwrap();
-    tera.add_template_file("templates/course.html", Some("course")).unwrap();
-    context.insert("courses", &course_list);
-    tera.render_to("course_list", &context, &mut buffer).unwrap();
-    let mut file = File::create("html/courses.html").expect("Failed to create file");
+/// Which courses a run of `archive` fetches.
+#[derive(Clone, Copy, Debug)]
+enum Run {
+    /// Every course
+    Full,
+    /// Courses whose schedule fires in this minute and that changed since they were archived
+    Incremental(DateTime<FixedOffset>),
+}
 
-    async fn getusername(id: String) -> String {
-        let secret = classroom1::oauth2::read_application_secret("credentials.json")
-        .await
-        .expect("client secret couldn't be read.");
-        let auth = classroom1::oauth2::InstalledFlowAuthenticator::builder(
-            secret,
-            classroom1::oauth2::InstalledFlowReturnMethod::HTTPRedirect,
-        )
-        .persist_tokens_to_disk("tokens.json")
-        .build()
-        .await
-        .expect("InstalledFlowAuthenticator failed to build");
-        let hub = Classroom::new(hyper::Client::builder().build(hyper_rustls::HttpsConnectorBuilder::new().with_native_roots().unwrap().https_or_http().enable_http1().build()), auth);
-        let user_profile = hub.user_profiles().get(&id).doit().await;
-        match user_profile {
-            Ok(profile) => {
-                
-                profile.1.name.unwrap().full_name.unwrap_or_else(|| "None".to_string())
+/// Archives on the configuration's schedules until the process is stopped.
+async fn daemon(cli: &Cli, config: &Config) {
+    // Already checked when the configuration was loaded
+    let timezone = config.render.timezone.as_deref().and_then(|timezone| timezone.parse().ok());
+    let daemon = &config.daemon;
+    let schedules: Vec<&Schedule> = [Some(&daemon.full), daemon.active.as_ref(), daemon.archived.as_ref()].into_iter()
+        .flatten()
+        .chain(config.courses.values().filter_map(|course| course.schedule.as_ref()))
+        .collect();
+    tracing::info!(
+        full = %daemon.full,
+        active = daemon.active.as_ref().map(ToString::to_string),
+        archived = daemon.archived.as_ref().map(ToString::to_string),
+        "Archiving on a schedule",
+    );
+    loop {
+        let now = now_in(timezone);
+        let Some(next) = schedules.iter().filter_map(|schedule| schedule.next_after(&now)).min() else {
+            tracing::error!("No schedule fires again");
+            return;
+        };
+        tracing::info!(at = %next, "Waiting for the next run");
+        tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+        let run = if daemon.full.matches(&next) { Run::Full } else { Run::Incremental(next) };
+        match RunLock::acquire(&config.lock_file()) {
+            Ok(_lock) => {
+                // One failed run shouldn't stop the ones after it
+                if let Err(error) = archive(cli, config, run).await {
+                    tracing::error!(run = ?run, error = %error, "Run failed");
+                }
             }
-            Err(_) => {
-                
-                "None".to_string()
-            },
+            Err(error) => tracing::warn!(error = %error, "Skipping a run, the archive is being written"),
         }
     }
+}
 
-    tera.register_function("getusername", move |args: &HashMap<String, Value>| {
-        if let Some(id) = args.get("id").and_then(|v| v.as_str()) {
-            let id = id.to_string(); // Clone the URL for async closure
-            let (sender, receiver) = mpsc::channel();
-            std::thread::spawn(move || {
-                let runtime = Runtime::new().unwrap();
-                let result = runtime.block_on(async move {
-                    getusername(id).await
-                });
-                let _ = sender.send(result);
-            });
-    
-            // Wait for the result from the spawned thread
-            let result = receiver.recv().unwrap();
-            Ok(Value::String(result))
-        } else {
-            Err(tera::Error::msg("No 'id' argument provided"))
-        }
-    });
-
-    async fn check_url(url: String) -> bool {
-        match reqwest::get(&url).await {
-            Ok(response) => response.status() == reqwest::StatusCode::OK,
-            Err(_) => false,
-        }
+async fn archive(cli: &Cli, config: &Config, run: Run) -> Result<(), Error> {
+    let run_time = Instant::now();
+    let redaction = match &cli.redaction {
+        Some(path) => Redaction::load(path)?,
+        None => Redaction::default(),
+    };
+    let theme = cli.theme.load()?;
+    // Each account signs in in turn, as the first run may have to ask in the browser
+    let mut signed_in = Vec::new();
+    for account in config.accounts() {
+        signed_in.push(connect(config, &account, &cli.features, &cli.limits, &cli.cache).await?);
     }
-    
-    // Register the Tera filter function
-    tera.register_function("url_ok", move |args: &HashMap<String, Value>| {
-        if let Some(url) = args.get("url").and_then(|v| v.as_str()) {
-            let url = url.to_string(); // Clone the URL for async closure
-            let (sender, receiver) = mpsc::channel();
-            std::thread::spawn(move || {
-                let runtime = Runtime::new().unwrap();
-                let result = runtime.block_on(async move {
-                    check_url(url).await
-                });
-                let _ = sender.send(result);
-            });
-    
-            // Wait for the result from the spawned thread
-            let result = receiver.recv().unwrap();
-    
-            Ok(Value::Bool(result))
-        } else {
-            Err(tera::Error::msg("No 'url' argument provided"))
-        }
-    });
+    let (course_list, owners) = assigned_courses(config, &signed_in, &cli.filter).await?;
 
-    /*tera.register_function("to_utc", move |args: &HashMap<String, Value>| {
-        if let Some(date) = args.get("date") {
-            let utc_date = "".to_string();
-            match date.year {
-                Some(year) => date_due.push_str(&year.to_string()),
-                None => Err(tera::Error::msg("No 'date.month' argument"))
-            }
-            date_due.push_str("-");
-            match date.month {
-                Some(month) => date_due.push_str(&month.to_string()),
-                None => Err(tera::Error::msg("No 'date.month' argument"))
+    // Incremental runs only fetch the due courses that changed, and leave every other page as it is
+    let (refresh, mut fingerprints) = match run {
+        Run::Full => (None, HashMap::new()),
+        Run::Incremental(at) => {
+            let fingerprints = load_fingerprints(&config.render.output);
+            let due: Vec<(&Course, &Account)> = course_list.iter().zip(&owners).filter(|(course, _)| {
+                let settings = config.course(course.id.as_deref().unwrap_or_default());
+                course_schedule(&config.daemon, &settings, course).is_some_and(|schedule| schedule.matches(&at))
+            }).map(|(course, &owner)| (course, &signed_in[owner])).collect();
+            let probes = futures::future::join_all(due.iter().map(|(course, account)| Fingerprint::probe(&account.api, course))).await;
+            let changed: HashSet<String> = due.iter().zip(probes).filter_map(|((course, _), probe)| {
+                let id = course.id.clone()?;
+                match probe {
+                    Ok(fingerprint) if fingerprints.get(&id) == Some(&fingerprint) => None,
+                    Ok(_) => Some(id),
+                    Err(error) => {
+                        tracing::warn!(id, error = %error, "Failed to check the course for changes, fetching it");
+                        Some(id)
+                    }
+                }
+            }).collect();
+            tracing::info!(due = due.len(), changed = changed.len(), "Checked for changes");
+            if changed.is_empty() {
+                return Ok(());
             }
-            date_due.push_str("-");
-            match date.day {
-                Some(day) => date_due.push_str(&day.to_string()),
-                None => Err(tera::Error::msg("No 'date.day' argument"))
-            }
-    
-            Ok(Value::Bool(result))
-        } else {
-            Err(tera::Error::msg("No 'date or time' argument provided"))
+            (Some(changed), fingerprints)
         }
-    });*/
-
-    file.write_all(&buffer).expect("Failed to write to file");
-    let mut reqquery_vec: Vec<Args> = Vec::new();
+    };
 
-    for course in response.1.courses.unwrap() {
-        let course_content = Args {
-            id: Some(course.clone().id.unwrap()),
-            name: Some(course.name.clone().unwrap_or_default()),
-            hub: Some(hub.clone()),
-            tera: Some(tera.clone()),
-        };
-        reqquery_vec.push(course_content);
+    let output = config.render.output.clone();
+    fs::create_dir_all(output.join("courses"))?;
+    theme.copy_static(&output)?;
+    // Keep the raw course list next to the pages so `export` can name courses in its manifest
+    let mut course_values: Vec<serde_json::Value> = course_list.iter().map(serde_json::to_value).collect::<Result<_, _>>()?;
+    for course in &mut course_values {
+        let rules = redaction.for_course(course["id"].as_str().unwrap_or_default());
+        rules.redact_value(course, &Roles::default());
     }
+    serde_json::to_writer_pretty(File::create(output.join("courses.json"))?, &course_values)?;
 
-    let tasks: Vec<_> = reqquery_vec.iter().map(|course| {
-        let course = course.clone();
-        tokio::spawn(async move {
-            let start_time = Instant::now();
-            let mut buffer = Vec::new();
-            let mut context = Context::new();
-            let string_id = course.clone().id.clone().unwrap();
-            let id = string_id.as_str();
-            let course_announcements = Some(course.hub.clone().unwrap().courses().announcements_list(id).doit().await.unwrap().1.announcements.clone().unwrap_or_default());
-            let course_work = Some(course.hub.clone().unwrap().courses().course_work_list(id).doit().await.unwrap().1.course_work.clone().unwrap_or_default());
-            let course_materials = Some(course.hub.clone().unwrap().courses().course_work_materials_list(id).doit().await.unwrap().1.course_work_material.clone().unwrap_or_default());
-            let name = Some(course.name.clone().unwrap_or_default());
-            let teachers = Some(course.hub.clone().unwrap().courses().teachers_list(id).doit().await.unwrap().1.teachers.clone().unwrap_or_default());
-            let topics = Some(course.hub.clone().unwrap().courses().topics_list(id).doit().await.unwrap().1.topic.clone().unwrap_or_default());
-            println!("Pulled Data From {}\nTook {:?}", course.name.clone().unwrap(), start_time.elapsed());
-            context.insert("name", &name.clone().unwrap());
-            if course_announcements.is_some() {
-                context.insert("course_announcements", &course_announcements.clone().unwrap());
-            }
-            if course_work.is_some() {
-                context.insert("coursework", &course_work.clone().unwrap());
+    let teras = templates(&theme, &signed_in, &cli.cache, &cli.embeds)?;
+    let groups = page_groups(config, &signed_in, &course_list, &owners, cli.merge_sections).await;
+    let site = Site {
+        accounts: signed_in,
+        teras,
+        theme,
+        redaction,
+        output,
+        students: cli.students,
+        embeds: cli.embeds.clone(),
+        cache: cli.cache.clone(),
+    };
+
+    let scheduler = Scheduler::new(cli.concurrency.clone());
+    let refreshed = |index: &usize| refresh.as_ref().is_none_or(|refresh| course_list[*index].id.as_ref().is_some_and(|id| refresh.contains(id)));
+    let tasks: Vec<_> = groups.iter().filter(|group| group.iter().any(refreshed)).map(|group| {
+        let page = site.page(config, &course_list, &owners, group);
+        let scheduler = scheduler.clone();
+        scheduler.clone().spawn_course(&page.account.api.clone(), move |api| async move { page.archive(api, &scheduler).await })
+    }).collect();
+    // A course that failed is still listed, just without its counts
+    let mut stats: HashMap<String, CourseStats> = HashMap::new();
+    for result in futures::future::join_all(tasks).await {
+        match result {
+            Ok(Ok(archived)) => {
+                stats.insert(archived.id, archived.stats);
+                fingerprints.extend(archived.fingerprints);
             }
-            if course_materials.is_some() {
-                context.insert("course_materials", &course_materials.clone().unwrap());
+            Ok(Err(error)) => {
+                tracing::error!(error = %error, "Failed to archive a course");
+                Metrics::add(&METRICS.course_failures, 1);
             }
-            if teachers.is_some() {
-                context.insert("teachers", &teachers.clone().unwrap());
+            Err(error) => {
+                tracing::error!(error = %error, "Course task failed");
+                Metrics::add(&METRICS.course_failures, 1);
             }
-            if topics.is_some() {
-                context.insert("topics", &topics.clone().unwrap());
+        }
+    }
+    if refresh.is_some() {
+        // Pages that weren't fetched again keep the counts they were archived with
+        let mut previous: HashMap<String, CourseStats> = fs::read(site.output.join(STATS_FILE)).ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default();
+        for group in &groups {
+            let id = course_list[group[0]].id.clone().unwrap_or_default();
+            if let Some(course_stats) = previous.remove(&id) {
+                stats.entry(id).or_insert(course_stats);
             }
-            //let course_work_student_submission_list: (Response<Body>, ListStudentSubmissionsResponse) = course.hub.clone().courses.unwrap().course_work_student_submissions_list(course_id: &id).doit().await.unwrap();
-            //println!("{:#?}", &context);
-            course.tera.unwrap().render_to("course", &context, &mut buffer).unwrap();
-            let mut file = File::create(format!("html/courses/{}.html", id)).expect("Failed to create file");
-            file.write_all(&buffer).expect("Failed to write to file");
-            println!("Course: {}, {}\nRender Time: {:?}", course.name.clone().unwrap(), id, start_time.elapsed());
-        })
+        }
+    }
+
+    // After the course pages, so it can link them
+    let taught: Vec<Vec<String>> = (0..site.accounts.len()).map(|account| {
+        let mut taught: Vec<String> = groups.iter()
+            .filter(|group| stats.get(course_list[group[0]].id.as_deref().unwrap_or_default()).is_some_and(|stats| stats.teaching))
+            .flat_map(|group| group.iter().filter(|&&index| owners[index] == account).filter_map(|&index| course_list[index].id.clone()))
+            .collect();
+        taught.sort();
+        taught
     }).collect();
-    futures::future::join_all(tasks).await;
+    let tera = &site.teras[0];
+    let mut invitations_page = false;
+    if let Some(invitations) = invitations(&site.accounts, &course_list, &taught, &site.output).await {
+        match invitations.write(tera, &site.theme, &site.output) {
+            Ok(()) => invitations_page = true,
+            Err(error) => tracing::error!(error = %error, "Failed to write the invitations page"),
+        }
+    }
 
+    // The index is rendered last so it can show what was pulled for each course
+    let mut buffer = Vec::new();
+    // Merged sections are listed once, under the course that names their page
+    let mut pages: Vec<Course> = groups.iter().map(|group| course_list[group[0]].clone()).collect();
+    redact_index(&mut pages, &mut stats, &site.redaction);
+    let mut context = index_context(&pages, &stats);
+    if invitations_page {
+        context.insert("invitations_page", INVITATIONS_PAGE);
+    }
+    site.theme.insert(&mut context, "");
+    tera.render_to("courses.html", &context, &mut buffer)?;
+    File::create(site.output.join("courses.html"))?.write_all(&buffer)?;
+    serde_json::to_writer_pretty(File::create(site.output.join(STATS_FILE))?, &stats)?;
+    if let Err(error) = save_fingerprints(&site.output, &fingerprints) {
+        tracing::error!(error = %error, "Failed to save the course fingerprints, the next incremental run fetches everything due");
+    }
+    METRICS.log_summary(run_time.elapsed());
+    Ok(())
 }
-
-//str - Stack allocated, not mutable (usually). have to know size at compile time.
-
-//String - Heap allocated, can be mutable (with 1 referance only OR Rust mem lock like RWlock), can grow or shrink size.
-
-//char - single character, including unicode, can be mutable
\ No newline at end of file
@@ -0,0 +1,161 @@
+use crate::redact::{CreatorNames, Redaction, Roles};
+use crate::term::{group_by_term, TermGroup};
+use classroom1::api::{Course, Teacher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tera::Context;
+
+/// Written next to courses.html so the index can be re-rendered (e.g. by `export`) without the API
+pub const STATS_FILE: &str = "stats.json";
+
+/// What the index page shows about a course beyond the `Course` itself, collected while the course is archived.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CourseStats {
+    pub owner_name: Option<String>,
+    pub announcements: usize,
+    pub coursework: usize,
+    /// Whether the authenticated user teaches the course, which decides if the enrollment code is shown
+    pub teaching: bool,
+    /// Sections merged into the course's page, empty when it has only its own
+    #[serde(default)]
+    pub sections: Vec<String>,
+    /// The account that archived the course, named when several are archived into the site
+    #[serde(default)]
+    pub account: Option<String>,
+}
+
+impl CourseStats {
+    /// Builds the stats for a course from its fetched teacher list.
+    ///
+    /// The owner is always one of the teachers, so their name comes from the roster instead of another profile lookup.
+    pub fn new(course: &Course, teachers: &[Teacher], me: Option<&str>, announcements: usize, coursework: usize) -> CourseStats {
+        let owner_name = teachers.iter()
+            .find(|teacher| teacher.user_id.is_some() && teacher.user_id == course.owner_id)
+            .and_then(|teacher| teacher.profile.as_ref()?.name.as_ref()?.full_name.clone());
+        let teaching = me.is_some_and(|me| {
+            course.owner_id.as_deref() == Some(me) || teachers.iter().any(|teacher| teacher.user_id.as_deref() == Some(me))
+        });
+        CourseStats { owner_name, announcements, coursework, teaching, sections: Vec::new(), account: None }
+    }
+}
+
+/// One block of the index page. Archived courses get their own block, which the template collapses.
+#[derive(Clone, Debug, Serialize)]
+pub struct IndexSection {
+    pub archived: bool,
+    pub terms: Vec<TermGroup>,
+}
+
+/// Applies each course's redaction rules to what the index shows of it: the course's own fields, like its room and
+/// heading, and its stats. Owners are left unnamed where creators are shown by role.
+pub fn redact_index(courses: &mut [Course], stats: &mut HashMap<String, CourseStats>, redaction: &Redaction) {
+    for course in courses {
+        let id = course.id.clone().unwrap_or_default();
+        let rules = redaction.for_course(&id);
+        if rules.is_noop() {
+            continue;
+        }
+        let mut value = serde_json::to_value(&*course).expect("a course serialises");
+        rules.redact_value(&mut value, &Roles::default());
+        *course = serde_json::from_value(value).expect("a redacted course is still a course");
+        if let Some(course_stats) = stats.get_mut(&id) {
+            if rules.creator_names == CreatorNames::Role {
+                course_stats.owner_name = None;
+            }
+            let mut value = serde_json::to_value(&*course_stats).expect("stats serialise");
+            rules.redact_value(&mut value, &Roles::default());
+            *course_stats = serde_json::from_value(value).expect("redacted stats are still stats");
+        }
+    }
+}
+
+/// Builds the context for courses.html: courses grouped by term, with archived courses split out at the end.
+pub fn index_context(courses: &[Course], stats: &HashMap<String, CourseStats>) -> Context {
+    let (archived, current): (Vec<Course>, Vec<Course>) = courses.iter()
+        .cloned()
+        .partition(|course| course.course_state.as_deref() == Some("ARCHIVED"));
+    let mut sections = vec![IndexSection { archived: false, terms: group_by_term(&current) }];
+    if !archived.is_empty() {
+        sections.push(IndexSection { archived: true, terms: group_by_term(&archived) });
+    }
+    let mut context = Context::new();
+    context.insert("courses", courses);
+    context.insert("sections", &sections);
+    context.insert("stats", stats);
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::tests::bundled;
+
+    fn course(id: &str, name: &str, state: &str) -> Course {
+        Course {
+            id: Some(id.to_string()),
+            name: Some(name.to_string()),
+            course_state: Some(state.to_string()),
+            enrollment_code: Some(format!("code{}", id)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn index_renders_terms_stats_and_archived_courses() {
+        let courses = [
+            course("1", "Biology Fall 2022", "ACTIVE"),
+            course("2", "Chemistry Spring 2023", "ACTIVE"),
+            course("3", "Homeroom", "ARCHIVED"),
+        ];
+        let stats = HashMap::from([
+            ("1".to_string(), CourseStats { owner_name: Some("Ada Lovelace".to_string()), announcements: 4, coursework: 2, teaching: true, ..Default::default() }),
+            ("2".to_string(), CourseStats { announcements: 1, ..Default::default() }),
+        ]);
+        let theme = bundled();
+        let mut context = index_context(&courses, &stats);
+        theme.insert(&mut context, "");
+        let html = theme.templates().unwrap().render("courses.html", &context).unwrap();
+
+        let position = |text: &str| html.find(text).unwrap_or_else(|| panic!("{} is missing", text));
+        // Newest term first, archived courses folded away at the end
+        assert!(position("Spring &#x27;23") < position("Fall &#x27;22"));
+        assert!(position("Fall &#x27;22") < position("<details class=\"archived\">"));
+        assert!(position("<details class=\"archived\">") < position("Homeroom"));
+        position("Owner: Ada Lovelace");
+        position("4 announcements, 2 assignments");
+        // The enrollment code is only shown for taught courses
+        position("Class Code: code1");
+        assert!(!html.contains("code2"));
+    }
+
+    #[test]
+    fn index_is_redacted() {
+        let email = regex::Regex::new(r"[A-Za-z0-9._%+\-]+@[A-Za-z0-9\-]+(?:\.[A-Za-z0-9\-]+)+").unwrap();
+        let mut courses = vec![Course {
+            room: Some("Ask teacher@school.org".to_string()),
+            section: Some("Period 1, office@school.org".to_string()),
+            description_heading: Some("Mail head@school.org".to_string()),
+            ..course("1", "Biology", "ACTIVE")
+        }];
+        let mut stats = HashMap::from([
+            ("1".to_string(), CourseStats { owner_name: Some("Ms Teacher (teacher@school.org)".to_string()), ..Default::default() }),
+        ]);
+        let render = |courses: &[Course], stats: &HashMap<String, CourseStats>| {
+            let theme = bundled();
+            let mut context = index_context(courses, stats);
+            theme.insert(&mut context, "");
+            theme.templates().unwrap().render("courses.html", &context).unwrap()
+        };
+        assert_eq!(email.find_iter(&render(&courses, &stats)).count(), 4);
+
+        let redaction: Redaction = toml::from_str("[default]\nemails = \"strip\"\n\n[courses.\"2\"]\ncreator_names = \"role\"\n").unwrap();
+        redact_index(&mut courses, &mut stats, &redaction);
+        let html = render(&courses, &stats);
+        assert!(!email.is_match(&html), "email left in:\n{}", html);
+        assert!(html.contains("Owner: Ms Teacher ([email removed])"));
+
+        let redaction: Redaction = toml::from_str("[default]\ncreator_names = \"role\"\n").unwrap();
+        redact_index(&mut courses, &mut stats, &redaction);
+        assert!(!render(&courses, &stats).contains("Owner:"));
+    }
+}
@@ -0,0 +1,182 @@
+//! The invitations page: courses the authenticated user is invited to, and for teachers, the invitations to their
+//! courses nobody has accepted yet.
+
+use crate::api::Api;
+use crate::profiles::Profiles;
+use crate::theme::Theme;
+use classroom1::api::{Course, Invitation};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tera::{Context, Tera};
+
+/// Written next to courses.html
+pub const INVITATIONS_PAGE: &str = "invitations.html";
+
+/// An invitation as the page shows it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct InvitationRow {
+    pub course_id: String,
+    /// The course's name, or its ID when the course can't be read
+    pub course_name: String,
+    /// `STUDENT`, `TEACHER` or `OWNER`
+    pub role: String,
+    /// Who was invited. For the user's own invitations, the account invited when several are archived
+    pub invitee: Option<String>,
+    /// The course's page in the archive, relative to the output directory, if it has one
+    pub page: Option<String>,
+}
+
+/// Lists every page of the invitations for `user_id` (`"me"` for the authenticated user) or to `course_id`.
+pub async fn list_invitations(api: &Api, user_id: Option<&str>, course_id: Option<&str>) -> classroom1::Result<Vec<Invitation>> {
+    let mut invitations = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let response = api.call(|| {
+            let mut call = api.hub().invitations().list().page_size(api.page_size());
+            if let Some(user_id) = user_id {
+                call = call.user_id(user_id);
+            }
+            if let Some(course_id) = course_id {
+                call = call.course_id(course_id);
+            }
+            if let Some(token) = &page_token {
+                call = call.page_token(token);
+            }
+            call.doit()
+        }).await?;
+        invitations.extend(response.invitations.unwrap_or_default());
+        page_token = response.next_page_token.filter(|token| !token.is_empty());
+        if page_token.is_none() {
+            return Ok(invitations);
+        }
+    }
+}
+
+/// Everything on the invitations page.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Invitations {
+    pub mine: Vec<InvitationRow>,
+    /// Invitations to the courses the user teaches, by course
+    pub outstanding: Vec<InvitationRow>,
+    /// Lists Classroom returned an error for, e.g. `"your invitations"` or a course name
+    pub failed: Vec<String>,
+}
+
+impl Invitations {
+    /// Fetches the user's invitations and those to each course in `taught`.
+    ///
+    /// Courses a user is only invited to aren't in their course list, so those are looked up one by one. Pages are
+    /// linked when `output` already has them.
+    pub async fn fetch(api: &Api, profiles: &Profiles, courses: &[Course], taught: &[String], output: &Path) -> Invitations {
+        let mut names: HashMap<String, String> = courses.iter()
+            .filter_map(|course| Some((course.id.clone()?, course.name.clone()?)))
+            .collect();
+        let mut invitations = Invitations::default();
+
+        let mine = list_invitations(api, Some("me"), None).await.unwrap_or_else(|error| {
+            tracing::warn!(error = %error, "Failed to list the user's invitations");
+            invitations.failed.push("your invitations".to_string());
+            Vec::new()
+        });
+        for invitation in &mine {
+            let id = invitation.course_id.clone().unwrap_or_default();
+            if !names.contains_key(&id) {
+                if let Ok(course) = api.call(|| api.hub().courses().get(&id).doit()).await {
+                    names.insert(id.clone(), course.name.unwrap_or_default());
+                }
+            }
+        }
+        invitations.mine = mine.into_iter().map(|invitation| row(invitation, &names, output)).collect();
+
+        for course_id in taught {
+            match list_invitations(api, None, Some(course_id)).await {
+                Ok(pending) => {
+                    for invitation in pending {
+                        let invitee = match invitation.user_id.as_deref() {
+                            Some(user_id) => profiles.name(user_id).await.or_else(|| Some(user_id.to_string())),
+                            None => None,
+                        };
+                        invitations.outstanding.push(InvitationRow { invitee, ..row(invitation, &names, output) });
+                    }
+                }
+                Err(error) => {
+                    tracing::warn!(course_id, error = %error, "Failed to list the course's invitations");
+                    invitations.failed.push(names.get(course_id).cloned().unwrap_or_else(|| course_id.clone()));
+                }
+            }
+        }
+        invitations
+    }
+
+    /// Renders invitations.html into `output`.
+    pub fn write(&self, tera: &Tera, theme: &Theme, output: &Path) -> io::Result<()> {
+        let mut context = Context::new();
+        context.insert("mine", &self.mine);
+        context.insert("outstanding", &self.outstanding);
+        context.insert("failed_sections", &self.failed);
+        theme.insert(&mut context, "");
+        let html = tera.render("invitations.html", &context).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {:?}", INVITATIONS_PAGE, e)))?;
+        fs::write(output.join(INVITATIONS_PAGE), html)
+    }
+}
+
+fn row(invitation: Invitation, names: &HashMap<String, String>, output: &Path) -> InvitationRow {
+    let course_id = invitation.course_id.unwrap_or_default();
+    let page = format!("courses/{}.html", course_id);
+    InvitationRow {
+        course_name: names.get(&course_id).cloned().unwrap_or_else(|| course_id.clone()),
+        page: output.join(&page).is_file().then_some(page),
+        role: invitation.role.unwrap_or_default(),
+        invitee: None,
+        course_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use crate::theme::tests::bundled;
+    use serde_json::json;
+
+    fn scratch() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("csee-invitations-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("courses")).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn invitations_link_archived_courses() {
+        // The mock ignores query strings, so the user's and the course's invitations come back the same
+        let server = MockServer::start().await;
+        server.route("/v1/invitations", json!({"invitations": [
+            {"id": "i1", "courseId": "1", "userId": "222", "role": "STUDENT"},
+            {"id": "i2", "courseId": "2", "userId": "222", "role": "TEACHER"},
+        ]}));
+        server.route("/v1/courses/2", json!({"id": "2", "name": "Chemistry"}));
+        server.route("/v1/userProfiles/222", json!({"id": "222", "name": {"fullName": "New Student"}}));
+        let api = server.api();
+        let output = scratch();
+        fs::write(output.join("courses/1.html"), "").unwrap();
+        let courses = [Course { id: Some("1".to_string()), name: Some("Biology".to_string()), ..Default::default() }];
+
+        let invitations = Invitations::fetch(&api, &Profiles::new(api.clone()), &courses, &["1".to_string()], &output).await;
+        assert!(invitations.failed.is_empty());
+        assert_eq!(invitations.mine[0].page.as_deref(), Some("courses/1.html"));
+        assert_eq!((invitations.mine[1].course_name.as_str(), invitations.mine[1].page.as_deref()), ("Chemistry", None));
+        assert!(invitations.mine[0].invitee.is_none());
+        assert_eq!(invitations.outstanding[0].invitee.as_deref(), Some("New Student"));
+
+        let theme = bundled();
+        invitations.write(&theme.templates().unwrap(), &theme, &output).unwrap();
+        let html = fs::read_to_string(output.join(INVITATIONS_PAGE)).unwrap();
+        // Autoescaping writes the slash as an entity, which browsers read back in attributes
+        assert!(html.contains(r#"href="courses&#x2F;1.html""#), "{}", html);
+        assert!(html.contains("Chemistry") && html.contains("New Student"));
+        fs::remove_dir_all(&output).unwrap();
+    }
+}
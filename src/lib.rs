@@ -0,0 +1,37 @@
+extern crate google_classroom1 as classroom1;
+use classroom1::Classroom;
+use classroom1::hyper::client::HttpConnector;
+use classroom1::hyper_rustls::HttpsConnector;
+
+pub mod accounts;
+pub mod aliases;
+pub mod api;
+pub mod archive;
+pub mod cache;
+pub mod config;
+pub mod daemon;
+pub mod embeds;
+pub mod fetch;
+pub mod filter;
+pub mod guardians;
+pub mod history;
+pub mod index;
+pub mod invitations;
+mod loopback;
+pub mod metrics;
+#[cfg(test)]
+mod mock;
+pub mod notifications;
+pub mod profiles;
+pub mod questions;
+pub mod redact;
+pub mod rich_text;
+pub mod roster;
+pub mod schedule;
+pub mod scopes;
+pub mod telemetry;
+pub mod term;
+pub mod theme;
+
+/// The Classroom hub every binary builds, over rustls with native roots
+pub type Hub = Classroom<HttpsConnector<HttpConnector>>;
@@ -0,0 +1,121 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters for the whole process, summed over every course and API call.
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Requests sent to the Classroom API, retries included
+    pub api_requests: AtomicU64,
+    pub retries: AtomicU64,
+    /// Calls that still failed after their retries
+    pub api_failures: AtomicU64,
+    /// Response bytes, from `Content-Length` or the size of the decoded JSON when the server didn't send one
+    pub bytes: AtomicU64,
+    /// Course sections left out of a page because their fetch failed
+    pub section_failures: AtomicU64,
+    /// Courses whose task panicked, so they have no page
+    pub course_failures: AtomicU64,
+    pub pages_rendered: AtomicU64,
+    pub render_micros: AtomicU64,
+}
+
+// name, help, prometheus type
+const DESCRIPTIONS: [(&str, &str, &str); 8] = [
+    ("api_requests_total", "Classroom API requests sent, retries included.", "counter"),
+    ("api_retries_total", "Classroom API requests retried after a quota or server error.", "counter"),
+    ("api_failures_total", "Classroom API calls that failed after all retries.", "counter"),
+    ("api_response_bytes_total", "Bytes received from the Classroom API.", "counter"),
+    ("section_failures_total", "Course sections not archived because their fetch failed.", "counter"),
+    ("course_failures_total", "Courses not archived because their task failed.", "counter"),
+    ("pages_rendered_total", "Course pages rendered.", "counter"),
+    ("render_seconds_total", "Time spent rendering course pages.", "counter"),
+];
+
+impl Metrics {
+    pub const fn new() -> Metrics {
+        Metrics {
+            api_requests: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            api_failures: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            section_failures: AtomicU64::new(0),
+            course_failures: AtomicU64::new(0),
+            pages_rendered: AtomicU64::new(0),
+            render_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add(counter: &AtomicU64, amount: u64) {
+        counter.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn record_render(&self, elapsed: Duration) {
+        Metrics::add(&self.pages_rendered, 1);
+        Metrics::add(&self.render_micros, elapsed.as_micros() as u64);
+    }
+
+    pub fn render_time(&self) -> Duration {
+        Duration::from_micros(self.render_micros.load(Ordering::Relaxed))
+    }
+
+    fn values(&self) -> [f64; 8] {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed) as f64;
+        [
+            load(&self.api_requests),
+            load(&self.retries),
+            load(&self.api_failures),
+            load(&self.bytes),
+            load(&self.section_failures),
+            load(&self.course_failures),
+            load(&self.pages_rendered),
+            self.render_time().as_secs_f64(),
+        ]
+    }
+
+    /// The counters in the Prometheus text exposition format, for the server's `/metrics`.
+    pub fn prometheus(&self) -> String {
+        let mut text = String::new();
+        for ((name, help, kind), value) in DESCRIPTIONS.iter().zip(self.values()) {
+            let _ = writeln!(text, "# HELP csee_{} {}", name, help);
+            let _ = writeln!(text, "# TYPE csee_{} {}", name, kind);
+            let _ = writeln!(text, "csee_{} {}", name, value);
+        }
+        text
+    }
+
+    /// Logs the end-of-run summary.
+    pub fn log_summary(&self, elapsed: Duration) {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        tracing::info!(
+            elapsed = ?elapsed,
+            api_requests = load(&self.api_requests),
+            retries = load(&self.retries),
+            api_failures = load(&self.api_failures),
+            bytes = load(&self.bytes),
+            section_failures = load(&self.section_failures),
+            course_failures = load(&self.course_failures),
+            pages_rendered = load(&self.pages_rendered),
+            render_time = ?self.render_time(),
+            "Run finished",
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prometheus_lists_every_counter() {
+        let metrics = Metrics::new();
+        Metrics::add(&metrics.api_requests, 3);
+        metrics.record_render(Duration::from_millis(1500));
+        let text = metrics.prometheus();
+        assert!(text.contains("# TYPE csee_api_requests_total counter\ncsee_api_requests_total 3\n"));
+        assert!(text.contains("csee_render_seconds_total 1.5\n"));
+        assert_eq!(text.lines().filter(|line| !line.starts_with('#')).count(), DESCRIPTIONS.len());
+    }
+}
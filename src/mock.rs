@@ -0,0 +1,148 @@
+//! A stand-in for the Classroom API, just enough HTTP for tests to point a hub at.
+
+use crate::api::{Api, Limits};
+use crate::loopback::{read_request, write_response, RawRequest};
+use crate::Hub;
+use classroom1::{hyper, hyper_rustls, Classroom};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Quota errors answered before any route, like the API does when a project runs out of requests.
+#[derive(Clone, Copy, Debug)]
+pub struct QuotaErrors {
+    /// How many requests fail before the server recovers
+    pub count: usize,
+    /// Seconds sent back as `Retry-After`, or as a `RetryInfo` detail for JSON errors
+    pub retry_after: Option<u64>,
+    /// Send a JSON error body like Google does, rather than a bare status
+    pub json: bool,
+}
+
+#[derive(Default)]
+struct State {
+    routes: HashMap<String, Value>,
+    quota_errors: Option<QuotaErrors>,
+    etags: HashMap<String, String>,
+    requests: usize,
+    not_modified: usize,
+}
+
+pub struct MockServer {
+    port: u16,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockServer {
+    pub async fn start() -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(State::default()));
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, server_state.clone()));
+            }
+        });
+        MockServer { port, state }
+    }
+
+    /// Answers GET requests for `path` with `body`. A `path` with a query string, e.g. `/v1/courses?pageToken=2`, only
+    /// answers requests that have all of its parameters, and comes before the same path without one.
+    pub fn route(&self, path: &str, body: Value) {
+        self.state.lock().unwrap().routes.insert(path.to_string(), body);
+    }
+
+    /// Sends `etag` with `path`'s route and answers 304 when a request already has it.
+    pub fn etag(&self, path: &str, etag: &str) {
+        self.state.lock().unwrap().etags.insert(path.to_string(), etag.to_string());
+    }
+
+    pub fn fail_with(&self, quota_errors: QuotaErrors) {
+        self.state.lock().unwrap().quota_errors = Some(quota_errors);
+    }
+
+    /// Requests received so far, failed ones included.
+    pub fn requests(&self) -> usize {
+        self.state.lock().unwrap().requests
+    }
+
+    /// Requests answered with 304 Not Modified.
+    pub fn not_modified(&self) -> usize {
+        self.state.lock().unwrap().not_modified
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/", self.port)
+    }
+
+    /// An `Api` on `hub`, unpaced and without retries, so a failure shows straight away.
+    pub fn api(&self) -> Api {
+        Api::new(self.hub(), Limits { requests_per_second: 0.0, max_retries: 0, ..Default::default() })
+    }
+
+    /// A hub that sends every request here, with a fixed token instead of OAuth.
+    pub fn hub(&self) -> Hub {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new().with_native_roots().unwrap().https_or_http().enable_http1().build();
+        let mut hub = Classroom::new(hyper::Client::builder().build(connector), "token".to_string());
+        hub.base_url(self.url());
+        hub
+    }
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+    let (status, headers, body) = respond(&mut state.lock().unwrap(), &request);
+    write_response(&mut stream, status, &headers, body.as_bytes()).await;
+}
+
+fn respond(state: &mut State, request: &RawRequest) -> (&'static str, Vec<(String, String)>, String) {
+    state.requests += 1;
+    let json_type = || ("Content-Type".to_string(), "application/json".to_string());
+    if let Some(quota_errors) = state.quota_errors.as_mut().filter(|quota_errors| quota_errors.count > 0) {
+        quota_errors.count -= 1;
+        let QuotaErrors { retry_after, json, .. } = *quota_errors;
+        if json {
+            let details: Vec<Value> = retry_after.into_iter().map(|seconds| json!({
+                "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                "retryDelay": format!("{}s", seconds),
+            })).collect();
+            let body = json!({"error": {
+                "code": 429,
+                "message": "Quota exceeded for quota metric 'Read requests'",
+                "status": "RESOURCE_EXHAUSTED",
+                "details": details,
+            }});
+            return ("429 Too Many Requests", vec![json_type()], body.to_string());
+        }
+        let headers = retry_after.into_iter().map(|seconds| ("Retry-After".to_string(), seconds.to_string())).collect();
+        return ("429 Too Many Requests", headers, "Quota exceeded".to_string());
+    }
+    let path = request.path();
+    let etag = state.etags.get(path).cloned();
+    if etag.is_some() && request.header("if-none-match") == etag.as_deref() {
+        state.not_modified += 1;
+        return ("304 Not Modified", Vec::new(), String::new());
+    }
+    match find_route(&state.routes, request) {
+        Some(body) => {
+            let mut headers = vec![json_type()];
+            headers.extend(etag.map(|etag| ("ETag".to_string(), etag)));
+            ("200 OK", headers, body.to_string())
+        }
+        None => ("404 Not Found", Vec::new(), "Not Found".to_string()),
+    }
+}
+
+fn find_route<'a>(routes: &'a HashMap<String, Value>, request: &RawRequest) -> Option<&'a Value> {
+    let query: Vec<&str> = request.target.split_once('?').map_or_else(Vec::new, |(_, query)| query.split('&').collect());
+    routes.iter()
+        .find_map(|(route, body)| {
+            let (path, params) = route.split_once('?')?;
+            (path == request.path() && params.split('&').all(|param| query.contains(&param))).then_some(body)
+        })
+        .or_else(|| routes.get(request.path()))
+}
@@ -0,0 +1,215 @@
+//! Classroom push notifications, so a course is fetched again when it changes rather than on every run.
+//!
+//! Classroom publishes changes to a Cloud Pub/Sub topic for each registered feed, and a push subscription on that
+//! topic delivers them to the server as JSON with the notification base64 encoded in `message.data`. `simulate`
+//! builds the same bodies, so the flow can be exercised without Pub/Sub.
+
+use crate::api::Api;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use classroom1::api::{CloudPubsubTopic, CourseRosterChangesInfo, CourseWorkChangesInfo, Feed, Registration};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// The per-course feeds a course page is built from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FeedKind {
+    /// Students and teachers joining or leaving
+    Roster,
+    /// Coursework created, changed or deleted
+    CourseWork,
+}
+
+impl FeedKind {
+    pub fn feed_type(self) -> &'static str {
+        match self {
+            FeedKind::Roster => "COURSE_ROSTER_CHANGES",
+            FeedKind::CourseWork => "COURSE_WORK_CHANGES",
+        }
+    }
+
+    /// The collection Classroom names in this feed's notifications.
+    fn collection(self) -> &'static str {
+        match self {
+            FeedKind::Roster => "courses.students",
+            FeedKind::CourseWork => "courses.courseWork",
+        }
+    }
+}
+
+/// Registers `topic`, e.g. `projects/my-project/topics/classroom`, for a course's `kind` feed.
+///
+/// Registrations expire after a week, so they need registering again; doing so while one is live renews it.
+pub async fn register(api: &Api, course_id: &str, topic: &str, kind: FeedKind) -> classroom1::Result<Registration> {
+    let request = Registration {
+        cloud_pubsub_topic: Some(CloudPubsubTopic { topic_name: Some(topic.to_string()) }),
+        feed: Some(Feed {
+            feed_type: Some(kind.feed_type().to_string()),
+            course_roster_changes_info: (kind == FeedKind::Roster).then(|| CourseRosterChangesInfo { course_id: Some(course_id.to_string()) }),
+            course_work_changes_info: (kind == FeedKind::CourseWork).then(|| CourseWorkChangesInfo { course_id: Some(course_id.to_string()) }),
+        }),
+        ..Default::default()
+    };
+    api.call(|| api.hub().registrations().create(request.clone()).doit()).await
+}
+
+/// The resource a notification is about.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceId {
+    pub course_id: String,
+    /// The coursework's ID, for coursework notifications
+    pub id: Option<String>,
+    /// The student's or teacher's ID, for roster notifications
+    pub user_id: Option<String>,
+}
+
+/// A change Classroom notified us of.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    /// e.g. `courses.courseWork` or `courses.students`
+    pub collection: String,
+    /// `CREATED`, `MODIFIED` or `DELETED`
+    pub event_type: String,
+    pub resource_id: ResourceId,
+}
+
+#[derive(Deserialize)]
+struct PushBody {
+    message: PushMessage,
+}
+
+#[derive(Deserialize)]
+struct PushMessage {
+    data: String,
+}
+
+/// Why a delivery couldn't be read.
+#[derive(Debug)]
+pub struct DeliveryError(String);
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid notification delivery: {}", self.0)
+    }
+}
+
+impl std::error::Error for DeliveryError {}
+
+impl Notification {
+    /// Reads a Pub/Sub push delivery.
+    pub fn from_delivery(body: &[u8]) -> Result<Notification, DeliveryError> {
+        let push: PushBody = serde_json::from_slice(body).map_err(|e| DeliveryError(e.to_string()))?;
+        let data = STANDARD.decode(push.message.data).map_err(|e| DeliveryError(format!("message.data: {}", e)))?;
+        let notification: Notification = serde_json::from_slice(&data).map_err(|e| DeliveryError(format!("message.data: {}", e)))?;
+        if notification.resource_id.course_id.is_empty() {
+            return Err(DeliveryError("no course ID".to_string()));
+        }
+        Ok(notification)
+    }
+}
+
+/// A push delivery like Pub/Sub sends, for a change of `kind` to `course_id`.
+pub fn simulate(course_id: &str, kind: FeedKind, event_type: &str) -> Value {
+    let notification = Notification {
+        collection: kind.collection().to_string(),
+        event_type: event_type.to_string(),
+        resource_id: ResourceId { course_id: course_id.to_string(), ..Default::default() },
+    };
+    let data = STANDARD.encode(serde_json::to_vec(&notification).expect("a notification serialises"));
+    json!({
+        "message": {"data": data, "messageId": format!("simulated-{}", rand::random::<u32>())},
+        "subscription": "projects/local/subscriptions/simulator",
+    })
+}
+
+/// Coalesces refreshes, so a burst of notifications for one course costs one or two fetches rather than one each.
+#[derive(Debug, Default)]
+pub struct Refreshes {
+    /// Courses being refreshed, and whether another notification came in meanwhile
+    running: Mutex<HashMap<String, bool>>,
+}
+
+impl Refreshes {
+    /// Whether to start refreshing `course_id`. When it's already being refreshed it is marked to go again instead.
+    pub fn begin(&self, course_id: &str) -> bool {
+        let mut running = self.running.lock().unwrap();
+        match running.get_mut(course_id) {
+            Some(again) => {
+                *again = true;
+                false
+            }
+            None => {
+                running.insert(course_id.to_string(), false);
+                true
+            }
+        }
+    }
+
+    /// Whether to refresh `course_id` once more, for notifications that came in during the last refresh.
+    pub fn finish(&self, course_id: &str) -> bool {
+        let mut running = self.running.lock().unwrap();
+        match running.get_mut(course_id) {
+            Some(again) if *again => {
+                *again = false;
+                true
+            }
+            _ => {
+                running.remove(course_id);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+
+    #[test]
+    fn simulated_deliveries_read_back() {
+        let body = simulate("42", FeedKind::CourseWork, "MODIFIED").to_string();
+        let notification = Notification::from_delivery(body.as_bytes()).unwrap();
+        assert_eq!(notification.resource_id.course_id, "42");
+        assert_eq!((notification.collection.as_str(), notification.event_type.as_str()), ("courses.courseWork", "MODIFIED"));
+
+        // As Classroom sends it
+        let data = STANDARD.encode(r#"{"collection":"courses.students","eventType":"CREATED","resourceId":{"courseId":"7","userId":"222"}}"#);
+        let body = json!({"message": {"data": data}}).to_string();
+        let notification = Notification::from_delivery(body.as_bytes()).unwrap();
+        assert_eq!(notification.resource_id.user_id.as_deref(), Some("222"));
+
+        assert!(Notification::from_delivery(br#"{"message": {"data": "not base64!"}}"#).is_err());
+        let data = STANDARD.encode(r#"{"collection":"courses.students","eventType":"CREATED","resourceId":{"courseId":""}}"#);
+        assert!(Notification::from_delivery(json!({"message": {"data": data}}).to_string().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn refreshes_coalesce() {
+        let refreshes = Refreshes::default();
+        assert!(refreshes.begin("1"));
+        assert!(!refreshes.begin("1"));
+        assert!(!refreshes.begin("1"));
+        assert!(refreshes.begin("2"));
+        // Two notifications during the first refresh make one more
+        assert!(refreshes.finish("1"));
+        assert!(!refreshes.finish("1"));
+        assert!(refreshes.begin("1"));
+    }
+
+    #[tokio::test]
+    async fn feeds_are_registered() {
+        let server = MockServer::start().await;
+        server.route("/v1/registrations", json!({"registrationId": "r1", "expiryTime": "2024-01-08T09:00:00Z"}));
+        let api = server.api();
+        let registration = register(&api, "1", "projects/p/topics/t", FeedKind::Roster).await.unwrap();
+        assert_eq!(registration.registration_id.as_deref(), Some("r1"));
+        assert_eq!(server.requests(), 1);
+    }
+}
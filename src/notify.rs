@@ -0,0 +1,56 @@
+use clap::Parser;
+use csee::notifications::{simulate, FeedKind};
+use csee::telemetry::Logging;
+
+/// Post a simulated Classroom change notification to the `test` server, as a Pub/Sub push subscription would
+#[derive(Parser)]
+#[command(name = "notify")]
+struct Cli {
+    /// Courses that changed, one delivery each
+    #[arg(required = true)]
+    courses: Vec<String>,
+    /// Which of the course's feeds the change comes from
+    #[arg(long, value_enum, default_value = "course-work")]
+    feed: FeedKind,
+    /// `CREATED`, `MODIFIED` or `DELETED`
+    #[arg(long, default_value = "MODIFIED")]
+    event: String,
+    /// Where to deliver to. Defaults to `/notifications` on `server.bind`, with `server.notification_token`.
+    #[arg(long)]
+    url: Option<String>,
+    #[command(flatten)]
+    logging: Logging,
+}
+
+#[tokio::main]
+async fn main() {
+    let (cli, config) = csee::config::parse::<Cli>();
+    cli.logging.init();
+    let url = cli.url.clone().unwrap_or_else(|| {
+        let mut url = format!("http://{}/notifications", config.server.bind);
+        if let Some(token) = &config.server.notification_token {
+            url = reqwest::Url::parse_with_params(&url, [("token", token)]).expect("a valid URL").to_string();
+        }
+        url
+    });
+    let client = reqwest::Client::new();
+    let mut failed = false;
+    for course_id in &cli.courses {
+        let delivery = simulate(course_id, cli.feed, &cli.event);
+        match client.post(&url).json(&delivery).send().await {
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                println!("{}: {} {}", course_id, status, body);
+                failed |= !status.is_success();
+            }
+            Err(error) => {
+                eprintln!("{}: failed to deliver to {}: {}", course_id, url, error);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::api::Api;
+use classroom1::api::{CourseWork, StudentSubmission};
+use serde::Serialize;
+use std::collections::HashMap;
+
+const MULTIPLE_CHOICE: &str = "MULTIPLE_CHOICE_QUESTION";
+const SHORT_ANSWER: &str = "SHORT_ANSWER_QUESTION";
+
+/// How the class answered one question. Only counts are kept, never who gave which answer.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Tally {
+    /// Students the question was assigned to
+    pub submissions: usize,
+    /// Students who have answered so far
+    pub answered: usize,
+    /// Every choice in order for multiple choice, the most common answers first for short answer
+    pub answers: Vec<AnswerCount>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AnswerCount {
+    pub answer: String,
+    pub count: usize,
+}
+
+pub fn is_question(work: &CourseWork) -> bool {
+    matches!(work.work_type.as_deref(), Some(MULTIPLE_CHOICE | SHORT_ANSWER))
+}
+
+/// Tallies the answers to one question.
+///
+/// Choices keep their order and show up even when nobody picked them. Short answers are grouped ignoring case and
+/// spacing, under the first spelling seen.
+pub fn tally(work: &CourseWork, submissions: &[StudentSubmission]) -> Tally {
+    let multiple_choice = work.work_type.as_deref() == Some(MULTIPLE_CHOICE);
+    let mut answers: Vec<AnswerCount> = work.multiple_choice_question.iter()
+        .filter(|_| multiple_choice)
+        .flat_map(|question| question.choices.iter().flatten())
+        .map(|choice| AnswerCount { answer: choice.clone(), count: 0 })
+        .collect();
+    let mut answered = 0;
+    for submission in submissions {
+        let answer = if multiple_choice {
+            submission.multiple_choice_submission.as_ref().and_then(|answer| answer.answer.as_deref())
+        } else {
+            submission.short_answer_submission.as_ref().and_then(|answer| answer.answer.as_deref())
+        };
+        let Some(answer) = answer.map(str::trim).filter(|answer| !answer.is_empty()) else {
+            continue;
+        };
+        answered += 1;
+        let key = normalise(answer);
+        match answers.iter_mut().find(|count| normalise(&count.answer) == key) {
+            Some(count) => count.count += 1,
+            // A choice edited after students answered still gets counted, after the current choices
+            None => answers.push(AnswerCount { answer: answer.to_string(), count: 1 }),
+        }
+    }
+    if !multiple_choice {
+        answers.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.answer.cmp(&b.answer)));
+    }
+    Tally { submissions: submissions.len(), answered, answers }
+}
+
+fn normalise(answer: &str) -> String {
+    answer.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Lists every page of submissions for one piece of coursework.
+pub async fn list_submissions(api: &Api, course_id: &str, course_work_id: &str) -> classroom1::Result<Vec<StudentSubmission>> {
+    let mut submissions = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let response = api.call(|| {
+            let mut call = api.hub().courses().course_work_student_submissions_list(course_id, course_work_id).page_size(api.page_size());
+            if let Some(token) = &page_token {
+                call = call.page_token(token);
+            }
+            call.doit()
+        }).await?;
+        submissions.extend(response.student_submissions.unwrap_or_default());
+        page_token = response.next_page_token.filter(|token| !token.is_empty());
+        if page_token.is_none() {
+            return Ok(submissions);
+        }
+    }
+}
+
+/// Tallies every question in `coursework`, keyed by coursework ID.
+///
+/// Only a teacher is sent the whole class's submissions; for a student this would tally their own answer.
+pub async fn tally_answers(api: &Api, course_id: &str, coursework: &[CourseWork]) -> classroom1::Result<HashMap<String, Tally>> {
+    let questions: Vec<(&str, &CourseWork)> = coursework.iter()
+        .filter(|work| is_question(work))
+        .filter_map(|work| Some((work.id.as_deref()?, work)))
+        .collect();
+    let submissions = futures::future::join_all(questions.iter().map(|(id, _)| list_submissions(api, course_id, id))).await;
+    let mut tallies = HashMap::new();
+    for ((id, work), submissions) in questions.into_iter().zip(submissions) {
+        tallies.insert(id.to_string(), tally(work, &submissions?));
+    }
+    Ok(tallies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use serde_json::json;
+
+    fn work(value: serde_json::Value) -> CourseWork {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn submissions(value: serde_json::Value) -> Vec<StudentSubmission> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn multiple_choice_keeps_every_choice_in_order() {
+        let question = work(json!({"workType": MULTIPLE_CHOICE, "multipleChoiceQuestion": {"choices": ["Red", "Green", "Blue"]}}));
+        let answers = submissions(json!([
+            {"multipleChoiceSubmission": {"answer": "Blue"}},
+            {"multipleChoiceSubmission": {"answer": "Red"}},
+            {"multipleChoiceSubmission": {"answer": "Blue"}},
+            {"multipleChoiceSubmission": {"answer": "Purple"}},
+            {"state": "CREATED"},
+        ]));
+
+        let tally = tally(&question, &answers);
+        assert_eq!((tally.submissions, tally.answered), (5, 4));
+        let counts: Vec<(&str, usize)> = tally.answers.iter().map(|count| (count.answer.as_str(), count.count)).collect();
+        assert_eq!(counts, vec![("Red", 1), ("Green", 0), ("Blue", 2), ("Purple", 1)]);
+    }
+
+    #[test]
+    fn short_answers_are_grouped_and_most_common_first() {
+        let question = work(json!({"workType": SHORT_ANSWER}));
+        let answers = submissions(json!([
+            {"shortAnswerSubmission": {"answer": "Paris"}},
+            {"shortAnswerSubmission": {"answer": "  new  york"}},
+            {"shortAnswerSubmission": {"answer": "New York"}},
+            {"shortAnswerSubmission": {"answer": " "}},
+        ]));
+
+        let tally = tally(&question, &answers);
+        assert_eq!(tally.answered, 3);
+        assert_eq!(tally.answers, vec![
+            AnswerCount { answer: "new  york".to_string(), count: 2 },
+            AnswerCount { answer: "Paris".to_string(), count: 1 },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn only_questions_are_fetched() {
+        let server = MockServer::start().await;
+        server.route("/v1/courses/1/courseWork/q/studentSubmissions", json!({"studentSubmissions": [
+            {"shortAnswerSubmission": {"answer": "42"}},
+        ]}));
+        let api = server.api();
+        let coursework = vec![
+            work(json!({"id": "q", "workType": SHORT_ANSWER})),
+            work(json!({"id": "w", "workType": "ASSIGNMENT"})),
+        ];
+
+        let tallies = tally_answers(&api, "1", &coursework).await.unwrap();
+        assert_eq!(tallies.len(), 1);
+        assert_eq!(tallies["q"].answered, 1);
+        assert_eq!(server.requests(), 1);
+    }
+}
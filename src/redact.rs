@@ -0,0 +1,387 @@
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::{fs, io};
+use tera::Context;
+
+lazy_static::lazy_static! {
+    static ref EMAIL: Regex = Regex::new(r"[A-Za-z0-9._%+\-]+@[A-Za-z0-9\-]+(?:\.[A-Za-z0-9\-]+)+").unwrap();
+}
+
+// Keys whose value is a Google user ID, wherever they appear in API data
+const USER_ID_KEYS: [&str; 6] = ["userId", "creatorUserId", "ownerId", "studentIds", "studentId", "guardianId"];
+// Keys holding a creator's user ID, which `creator_names = "role"` turns into a `creatorRole`
+const CREATOR_KEYS: [&str; 1] = ["creatorUserId"];
+
+/// What to do with a sensitive field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldAction {
+    #[default]
+    Keep,
+    /// Remove the field, or blank out the value when it is embedded in text
+    Strip,
+    /// Replace the value with a short salted hash, so the same person still lines up across pages
+    Hash,
+}
+
+/// How creators of posts are shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CreatorNames {
+    #[default]
+    Keep,
+    /// "Teacher" or "Student" instead of the person's name
+    Role,
+}
+
+/// Redaction rules for one course. The default keeps everything, matching archives made before redaction existed.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    pub emails: FieldAction,
+    pub user_ids: FieldAction,
+    /// `hash` is treated like `strip`, a hashed URL is of no use on a page
+    pub photos: FieldAction,
+    /// Drop any `privateComments` the data carries. Classroom only shows private comments to the teacher and the student.
+    pub drop_private_comments: bool,
+    pub creator_names: CreatorNames,
+    /// Mixed into every hash so IDs can't be confirmed by hashing a known one
+    pub salt: String,
+}
+
+/// Per-course overrides. Anything left out falls back to the `[default]` rules.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesOverride {
+    pub emails: Option<FieldAction>,
+    pub user_ids: Option<FieldAction>,
+    pub photos: Option<FieldAction>,
+    pub drop_private_comments: Option<bool>,
+    pub creator_names: Option<CreatorNames>,
+}
+
+/// The redaction file passed with `--redaction`.
+///
+/// ```toml
+/// [default]
+/// emails = "strip"
+/// user_ids = "hash"
+/// photos = "strip"
+/// drop_private_comments = true
+/// creator_names = "role"
+///
+/// [courses."581959133980"]
+/// creator_names = "keep"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Redaction {
+    pub default: Rules,
+    pub courses: HashMap<String, RulesOverride>,
+}
+
+impl Redaction {
+    pub fn load(path: &Path) -> io::Result<Redaction> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn for_course(&self, course_id: &str) -> Rules {
+        let mut rules = self.default.clone();
+        if let Some(course) = self.courses.get(course_id) {
+            rules.emails = course.emails.unwrap_or(rules.emails);
+            rules.user_ids = course.user_ids.unwrap_or(rules.user_ids);
+            rules.photos = course.photos.unwrap_or(rules.photos);
+            rules.drop_private_comments = course.drop_private_comments.unwrap_or(rules.drop_private_comments);
+            rules.creator_names = course.creator_names.unwrap_or(rules.creator_names);
+        }
+        rules
+    }
+}
+
+/// Who is who in a course, used to turn creator IDs into roles.
+#[derive(Clone, Debug, Default)]
+pub struct Roles {
+    pub teachers: HashSet<String>,
+    pub students: HashSet<String>,
+}
+
+impl Roles {
+    /// Collects user IDs from the `teachers` and `students` lists of a course context.
+    pub fn from_json(context: &Value) -> Roles {
+        let ids = |key: &str| -> HashSet<String> {
+            context.get(key).and_then(Value::as_array).into_iter().flatten()
+                .filter_map(|member| member.get("userId")?.as_str().map(str::to_string))
+                .collect()
+        };
+        Roles { teachers: ids("teachers"), students: ids("students") }
+    }
+
+    fn role_of(&self, user_id: &str) -> &'static str {
+        if self.teachers.contains(user_id) {
+            "Teacher"
+        } else if self.students.contains(user_id) {
+            "Student"
+        } else {
+            "Classroom member"
+        }
+    }
+}
+
+impl Rules {
+    pub fn is_noop(&self) -> bool {
+        self.emails == FieldAction::Keep
+            && self.user_ids == FieldAction::Keep
+            && self.photos == FieldAction::Keep
+            && !self.drop_private_comments
+            && self.creator_names == CreatorNames::Keep
+    }
+
+    /// Whether post authors have to be named before redacting, because their user IDs won't survive it.
+    pub fn hides_creator_ids(&self) -> bool {
+        self.user_ids != FieldAction::Keep && self.creator_names == CreatorNames::Keep
+    }
+
+    /// Redacts a course page context right before it is rendered.
+    pub fn redact_context(&self, context: Context) -> Context {
+        if self.is_noop() {
+            return context;
+        }
+        let mut value = context.into_json();
+        let roles = Roles::from_json(&value);
+        self.redact_value(&mut value, &roles);
+        Context::from_value(value).expect("a redacted context is still an object")
+    }
+
+    /// Redacts any API data in place, e.g. a course before it is written to courses.json.
+    pub fn redact_value(&self, value: &mut Value, roles: &Roles) {
+        match value {
+            Value::Object(map) => self.redact_object(map, roles),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_value(item, roles)),
+            Value::String(text) if self.emails != FieldAction::Keep && EMAIL.is_match(text) => {
+                *text = EMAIL.replace_all(text, |caps: &regex::Captures| self.replace_email(&caps[0])).into_owned();
+            }
+            _ => (),
+        }
+    }
+
+    fn redact_object(&self, map: &mut Map<String, Value>, roles: &Roles) {
+        if self.drop_private_comments {
+            map.remove("privateComments");
+        }
+        if self.photos != FieldAction::Keep {
+            map.remove("photoUrl");
+        }
+        if self.creator_names == CreatorNames::Role {
+            for key in CREATOR_KEYS {
+                if let Some(Value::String(id)) = map.remove(key) {
+                    map.insert("creatorRole".to_string(), Value::String(roles.role_of(&id).to_string()));
+                }
+            }
+        }
+        if self.user_ids != FieldAction::Keep {
+            for key in USER_ID_KEYS {
+                self.redact_user_id(map, key);
+            }
+            // A profile's own `id` is the user ID too
+            for key in ["profile", "guardianProfile"] {
+                if let Some(Value::Object(profile)) = map.get_mut(key) {
+                    self.redact_user_id(profile, "id");
+                }
+            }
+        }
+        for (_, child) in map.iter_mut() {
+            self.redact_value(child, roles);
+        }
+    }
+
+    fn redact_user_id(&self, map: &mut Map<String, Value>, key: &str) {
+        if self.user_ids == FieldAction::Strip {
+            map.remove(key);
+            return;
+        }
+        match map.get_mut(key) {
+            Some(Value::String(id)) => *id = self.hash("user", id),
+            Some(Value::Array(ids)) => {
+                for id in ids.iter_mut() {
+                    if let Value::String(id) = id {
+                        *id = self.hash("user", id);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn replace_email(&self, email: &str) -> String {
+        match self.emails {
+            FieldAction::Hash => self.hash("email", &email.to_lowercase()),
+            _ => "[email removed]".to_string(),
+        }
+    }
+
+    // Hashes never contain '@', so a hashed email can't be mistaken for (or mailed as) an address
+    fn hash(&self, kind: &str, value: &str) -> String {
+        let digest = Sha256::digest(format!("{}:{}", self.salt, value).as_bytes());
+        let hex: String = digest.iter().take(6).map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}-{}", kind, hex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::tests::bundled;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn course_context() -> Context {
+        Context::from_value(json!({
+            "name": "EN201 (Sem 2 2022-23)",
+            "teachers": [{
+                "userId": "111",
+                "profile": {
+                    "id": "111",
+                    "name": {"fullName": "Ms Teacher"},
+                    "emailAddress": "teacher@school.org",
+                    "photoUrl": "//lh3.googleusercontent.com/a/teacher",
+                    "verifiedTeacher": true,
+                },
+            }],
+            "students": [{
+                "userId": "222",
+                "profile": {"id": "222", "name": {"fullName": "A Student"}, "emailAddress": "student@school.org"},
+            }],
+            "course_announcements": [{
+                "alternateLink": "https://classroom.google.com/c/1/p/2",
+                "creatorUserId": "111",
+                "creationTime": "2023-01-02T10:00:00Z",
+                "text": "Questions? Email me at Teacher@School.org or the office (office@school.org).",
+            }],
+            "coursework": [{
+                "title": "Essay",
+                "creatorUserId": "222",
+                "description": "Send drafts to teacher@school.org",
+                "individualStudentsOptions": {"studentIds": ["222"]},
+            }],
+        })).unwrap()
+    }
+
+    fn render(context: &Context) -> String {
+        render_page("course.html", context)
+    }
+
+    fn render_page(template: &str, context: &Context) -> String {
+        let theme = bundled();
+        let mut tera = theme.templates().unwrap();
+        let mut context = context.clone();
+        theme.insert(&mut context, "../");
+        // Stands in for the profile lookup, which would otherwise hit the API
+        tera.register_function("getusername", |args: &HashMap<String, Value>| {
+            Ok(Value::String(format!("Name of {}", args["id"].as_str().unwrap())))
+        });
+        tera.register_function("url_ok", |_: &HashMap<String, Value>| Ok(Value::Bool(true)));
+        tera.render(template, &context).unwrap()
+    }
+
+    #[test]
+    fn unredacted_page_contains_emails() {
+        // Guards the tests below against passing only because the template stopped printing emails
+        assert!(EMAIL.is_match(&render(&course_context())));
+    }
+
+    #[test]
+    fn stripped_emails_never_reach_the_page() {
+        let rules = Rules { emails: FieldAction::Strip, ..Default::default() };
+        let html = render(&rules.redact_context(course_context()));
+        assert!(!EMAIL.is_match(&html), "email left in:\n{}", html);
+        assert!(html.contains("[email removed]"));
+    }
+
+    #[test]
+    fn hashed_emails_never_reach_the_page() {
+        let rules = Rules { emails: FieldAction::Hash, salt: "pepper".to_string(), ..Default::default() };
+        let html = render(&rules.redact_context(course_context()));
+        assert!(!EMAIL.is_match(&html), "email left in:\n{}", html);
+        // Case differences don't split one address into two hashes
+        assert_eq!(html.matches(&rules.hash("email", "teacher@school.org")).count(), 3);
+    }
+
+    #[test]
+    fn user_ids_and_photos_are_removed() {
+        let rules = Rules { user_ids: FieldAction::Hash, photos: FieldAction::Strip, ..Default::default() };
+        let value = rules.redact_context(course_context()).into_json();
+        let text = value.to_string();
+        assert!(!text.contains("\"111\"") && !text.contains("\"222\""));
+        assert!(!text.contains("photoUrl"));
+        assert_eq!(value["coursework"][0]["individualStudentsOptions"]["studentIds"][0], json!(rules.hash("user", "222")));
+    }
+
+    #[test]
+    fn guardians_page_is_redacted() {
+        let context = Context::from_value(json!({
+            "name": "EN201 (Sem 2 2022-23)",
+            "course_page": "1.html",
+            "guardians": [{
+                "student": {"userId": "222", "profile": {"name": {"fullName": "A Student"}}},
+                "guardians": [{
+                    "studentId": "222",
+                    "guardianId": "333",
+                    "guardianProfile": {"id": "333", "name": {"fullName": "A Parent"}, "emailAddress": "parent@home.org"},
+                }],
+                "invitations": [{"studentId": "222", "invitedEmailAddress": "carer@home.org", "state": "PENDING"}],
+            }],
+        })).unwrap();
+        let html = render_page("guardians.html", &context);
+        assert!(html.contains("parent@home.org") && html.contains("carer@home.org"));
+
+        let rules = Rules { emails: FieldAction::Strip, user_ids: FieldAction::Hash, ..Default::default() };
+        let value = rules.redact_context(context.clone()).into_json();
+        assert!(!value.to_string().contains("\"333\"") && !value.to_string().contains("\"222\""));
+        let html = render_page("guardians.html", &rules.redact_context(context));
+        assert!(!EMAIL.is_match(&html), "email left in:\n{}", html);
+        assert!(html.contains("A Parent"));
+    }
+
+    #[test]
+    fn hashed_creators_keep_their_names() {
+        let rules = Rules { user_ids: FieldAction::Hash, ..Default::default() };
+        assert!(rules.hides_creator_ids());
+        // As `Profiles::name_creators` leaves it, before redacting
+        let mut value = course_context().into_json();
+        value["course_announcements"][0]["creatorName"] = json!("Ms Teacher");
+        value["coursework"][0]["creatorName"] = json!("None");
+        let html = render(&rules.redact_context(Context::from_value(value).unwrap()));
+        assert!(html.contains("Posted By: Ms Teacher"));
+        // Neither post is looked up by its hashed ID
+        assert!(!html.contains("Name of"), "{}", html);
+    }
+
+    #[test]
+    fn creators_become_roles() {
+        let rules = Rules { creator_names: CreatorNames::Role, ..Default::default() };
+        let html = render(&rules.redact_context(course_context()));
+        assert!(html.contains("Posted By: Teacher"));
+        assert!(!html.contains("Name of 111"));
+    }
+
+    #[test]
+    fn course_overrides_fall_back_to_default() {
+        let redaction: Redaction = toml::from_str(r#"
+            [default]
+            emails = "strip"
+            creator_names = "role"
+
+            [courses."42"]
+            emails = "keep"
+        "#).unwrap();
+        let rules = redaction.for_course("42");
+        assert_eq!(rules.emails, FieldAction::Keep);
+        assert_eq!(rules.creator_names, CreatorNames::Role);
+        assert_eq!(redaction.for_course("7").emails, FieldAction::Strip);
+    }
+}
@@ -0,0 +1,146 @@
+//! Plain text from the API, such as announcement text and coursework descriptions, as HTML that is safe to put in a page.
+
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+
+lazy_static::lazy_static! {
+    // Quotes and angle brackets end a URL, so a link can never carry markup with it
+    static ref URL: Regex = Regex::new(r#"(?i)\bhttps?://[^\s<>"']+"#).unwrap();
+}
+
+/// Escapes text for HTML content and for attribute values in double or single quotes.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// Formats plain text as paragraphs: blank lines separate paragraphs, single line breaks become `<br>`, and http(s)
+/// URLs become links. Everything else is escaped, so no markup in the text survives.
+pub fn to_html(text: &str) -> String {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let mut paragraphs: Vec<Vec<&str>> = Vec::new();
+    let mut paragraph = Vec::new();
+    for line in text.split('\n') {
+        if line.trim().is_empty() {
+            if !paragraph.is_empty() {
+                paragraphs.push(std::mem::take(&mut paragraph));
+            }
+        } else {
+            paragraph.push(line.trim_end());
+        }
+    }
+    if !paragraph.is_empty() {
+        paragraphs.push(paragraph);
+    }
+    paragraphs.iter()
+        .map(|lines| format!("<p>{}</p>", lines.iter().map(|line| linkify(line)).collect::<Vec<_>>().join("<br>\n")))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn linkify(line: &str) -> String {
+    let mut html = String::new();
+    let mut rest = 0;
+    for found in URL.find_iter(line) {
+        let url = trim_url(found.as_str());
+        if url.split_once("://").is_none_or(|(_, host)| host.is_empty()) {
+            continue;
+        }
+        html.push_str(&escape(&line[rest..found.start()]));
+        rest = found.start() + url.len();
+        let url = escape(url);
+        html.push_str(&format!(r#"<a href="{}" rel="noopener noreferrer">{}</a>"#, url, url));
+    }
+    html.push_str(&escape(&line[rest..]));
+    html
+}
+
+/// Drops punctuation that ends the sentence rather than the URL, e.g. "see https://example.com/a_(b)."
+fn trim_url(mut url: &str) -> &str {
+    while let Some(last) = url.chars().last() {
+        let unbalanced = last == ')' && url.matches('(').count() < url.matches(')').count();
+        if !matches!(last, '.' | ',' | ';' | ':' | '!' | '?') && !unbalanced {
+            break;
+        }
+        url = &url[..url.len() - 1];
+    }
+    url
+}
+
+/// The `rich_text` Tera filter. Its output is already escaped, so autoescaping leaves it alone.
+pub struct RichText;
+
+impl tera::Filter for RichText {
+    fn filter(&self, value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let text = value.as_str().ok_or_else(|| tera::Error::msg("rich_text needs a string"))?;
+        Ok(Value::String(to_html(text)))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_paragraphs_and_line_breaks() {
+        let html = to_html("Hello class,\r\nwelcome back.\r\n\r\n  \n\nSee you\nMonday\n");
+        assert_eq!(html, "<p>Hello class,<br>\nwelcome back.</p>\n<p>See you<br>\nMonday</p>");
+        assert_eq!(to_html(" \n\n"), "");
+    }
+
+    #[test]
+    fn links_urls_without_trailing_punctuation() {
+        assert_eq!(
+            to_html("Read https://en.wikipedia.org/wiki/Rust_(language). Then (https://example.com/a?b=1&c=2)!"),
+            "<p>Read <a href=\"https://en.wikipedia.org/wiki/Rust_(language)\" rel=\"noopener noreferrer\">\
+             https://en.wikipedia.org/wiki/Rust_(language)</a>. Then (<a href=\"https://example.com/a?b=1&amp;c=2\" \
+             rel=\"noopener noreferrer\">https://example.com/a?b=1&amp;c=2</a>)!</p>"
+        );
+        assert_eq!(to_html("just http:// here"), "<p>just http:// here</p>");
+    }
+
+    #[test]
+    fn markup_in_the_text_is_escaped() {
+        let html = to_html("<script>alert('hi')</script> & <img src=x onerror=alert(1)>");
+        assert_eq!(html, "<p>&lt;script&gt;alert(&#x27;hi&#x27;)&lt;/script&gt; &amp; &lt;img src=x onerror=alert(1)&gt;</p>");
+    }
+
+    #[test]
+    fn urls_cannot_break_out_of_the_href() {
+        let html = to_html(r#"https://example.com/"onmouseover="alert(1) https://example.com/'><script>x</script>"#);
+        assert!(!html.contains("<script"), "{}", html);
+        assert!(!html.contains("\"onmouseover"), "{}", html);
+        assert!(html.contains(r#"<a href="https://example.com/" rel="noopener noreferrer">"#), "{}", html);
+        // Only http and https are linked
+        assert!(!to_html("javascript:alert(1) data:text/html,<b>x</b>").contains("<a"));
+        assert!(!to_html("xhttps://example.com").contains("<a"));
+    }
+
+    #[test]
+    fn the_filter_is_not_escaped_twice() {
+        let mut tera = tera::Tera::default();
+        tera.add_raw_template("page.html", "<div>{{ text | rich_text }}</div>").unwrap();
+        tera.register_filter("rich_text", RichText);
+        let mut context = tera::Context::new();
+        context.insert("text", "a < b\nhttps://example.com");
+        assert_eq!(
+            tera.render("page.html", &context).unwrap(),
+            "<div><p>a &lt; b<br>\n<a href=\"https://example.com\" rel=\"noopener noreferrer\">https://example.com</a></p></div>"
+        );
+    }
+}
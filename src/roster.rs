@@ -0,0 +1,142 @@
+use crate::api::Api;
+use classroom1::api::{Name, Student, UserProfile};
+use serde::Deserialize;
+
+/// How much of the student roster ends up in the archive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StudentPrivacy {
+    /// Students are not fetched at all
+    #[default]
+    Omit,
+    /// Students are listed as "Student 1", "Student 2", ... without email or photo
+    Pseudonymise,
+    /// Name, email and photo as returned by the API
+    Full,
+}
+
+/// Lists every page of a course's students.
+pub async fn list_students(api: &Api, course_id: &str) -> classroom1::Result<Vec<Student>> {
+    let mut students = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let response = api.call(|| {
+            let mut call = api.hub().courses().students_list(course_id).page_size(api.page_size());
+            if let Some(token) = &page_token {
+                call = call.page_token(token);
+            }
+            call.doit()
+        }).await?;
+        students.extend(response.students.unwrap_or_default());
+        page_token = response.next_page_token.filter(|token| !token.is_empty());
+        if page_token.is_none() {
+            return Ok(students);
+        }
+    }
+}
+
+/// Applies the privacy setting to a fetched roster, sorted the way the roster section shows it.
+pub fn apply_privacy(students: Vec<Student>, privacy: StudentPrivacy) -> Vec<Student> {
+    with_privacy(students, privacy).into_iter().map(|(_, student)| student).collect()
+}
+
+/// Like `apply_privacy`, with each student's user ID from before the setting was applied, so other data about a
+/// student can still be matched up with how the roster shows them.
+pub fn with_privacy(mut students: Vec<Student>, privacy: StudentPrivacy) -> Vec<(Option<String>, Student)> {
+    match privacy {
+        StudentPrivacy::Omit => Vec::new(),
+        StudentPrivacy::Full => {
+            students.sort_by_key(|student| full_name(student).map(str::to_lowercase));
+            students.into_iter().map(|student| (student.user_id.clone(), student)).collect()
+        }
+        StudentPrivacy::Pseudonymise => {
+            // Numbered by user ID rather than name so a student keeps their number between runs
+            students.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+            students.into_iter().enumerate().map(|(number, student)| (student.user_id, Student {
+                course_id: student.course_id,
+                profile: Some(UserProfile {
+                    name: Some(Name {
+                        full_name: Some(format!("Student {}", number + 1)),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                student_work_folder: None,
+                user_id: None,
+            })).collect()
+        }
+    }
+}
+
+fn full_name(student: &Student) -> Option<&str> {
+    student.profile.as_ref()?.name.as_ref()?.full_name.as_deref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use serde_json::json;
+
+    fn student(id: &str, name: &str) -> Student {
+        Student {
+            course_id: Some("1".to_string()),
+            user_id: Some(id.to_string()),
+            profile: Some(UserProfile {
+                id: Some(id.to_string()),
+                name: Some(Name { full_name: Some(name.to_string()), ..Default::default() }),
+                email_address: Some(format!("{}@school.org", id)),
+                photo_url: Some(format!("https://photos.example/{}", id)),
+                ..Default::default()
+            }),
+            student_work_folder: None,
+        }
+    }
+
+    fn names(students: &[Student]) -> Vec<&str> {
+        students.iter().filter_map(full_name).collect()
+    }
+
+    #[test]
+    fn full_rosters_are_sorted_by_name() {
+        let students = vec![student("3", "zoe Adams"), student("1", "Ben Carter"), student("2", "Amy Brown")];
+        let roster = with_privacy(students, StudentPrivacy::Full);
+        let ids: Vec<_> = roster.iter().map(|(id, _)| id.as_deref().unwrap()).collect();
+        assert_eq!(ids, ["2", "1", "3"]);
+        let students: Vec<Student> = roster.into_iter().map(|(_, student)| student).collect();
+        assert_eq!(names(&students), ["Amy Brown", "Ben Carter", "zoe Adams"]);
+        assert_eq!(students[0].profile.as_ref().unwrap().email_address.as_deref(), Some("2@school.org"));
+    }
+
+    #[test]
+    fn pseudonyms_are_numbered_by_user_id_without_contact_details() {
+        let students = vec![student("b", "Amy Brown"), student("c", "Ben Carter"), student("a", "Zoe Adams")];
+        let roster = with_privacy(students, StudentPrivacy::Pseudonymise);
+        let ids: Vec<_> = roster.iter().map(|(id, _)| id.as_deref().unwrap()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        let students: Vec<Student> = roster.into_iter().map(|(_, student)| student).collect();
+        assert_eq!(names(&students), ["Student 1", "Student 2", "Student 3"]);
+        for student in &students {
+            let profile = student.profile.as_ref().unwrap();
+            assert_eq!((&profile.id, &profile.email_address, &profile.photo_url), (&None, &None, &None));
+            assert_eq!(student.user_id, None);
+        }
+        assert!(apply_privacy(vec![student("a", "Zoe Adams")], StudentPrivacy::Omit).is_empty());
+    }
+
+    #[tokio::test]
+    async fn every_page_of_students_is_listed() {
+        let server = MockServer::start().await;
+        server.route("/v1/courses/1/students", json!({
+            "students": [{"userId": "1", "profile": {"name": {"fullName": "Amy Brown"}}}],
+            "nextPageToken": "2",
+        }));
+        server.route("/v1/courses/1/students?pageToken=2", json!({
+            "students": [{"userId": "2", "profile": {"name": {"fullName": "Ben Carter"}}}],
+        }));
+        let api = server.api();
+        let students = list_students(&api, "1").await.unwrap();
+        assert_eq!(names(&students), ["Amy Brown", "Ben Carter"]);
+        assert_eq!(server.requests(), 2);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::api::Api;
+use crate::metrics::METRICS;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// How much work runs at once. Fetching and rendering are limited separately.
+#[derive(Clone, Debug, clap::Args)]
+pub struct Concurrency {
+    /// Courses fetched at the same time
+    #[arg(long, default_value_t = 4)]
+    pub max_courses: usize,
+    /// Requests one course has in flight at the same time, within the shared --max-in-flight
+    #[arg(long, default_value_t = 3)]
+    pub max_requests_per_course: usize,
+    /// Runtime threads driving API requests
+    #[arg(long, default_value_t = 4)]
+    pub worker_threads: usize,
+    /// Pages rendered at the same time, each on its own blocking thread
+    #[arg(long, default_value_t = 2)]
+    pub render_threads: usize,
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Concurrency { max_courses: 4, max_requests_per_course: 3, worker_threads: 4, render_threads: 2 }
+    }
+}
+
+impl Concurrency {
+    /// The one runtime a binary runs on. Page rendering goes to its blocking pool through `Scheduler::render`.
+    pub fn runtime(&self) -> io::Result<Runtime> {
+        Builder::new_multi_thread()
+            .worker_threads(self.worker_threads.max(1))
+            .enable_all()
+            .build()
+    }
+}
+
+/// Runs course tasks within the `Concurrency` limits.
+#[derive(Clone)]
+pub struct Scheduler {
+    concurrency: Concurrency,
+    courses: Arc<Semaphore>,
+    renders: Arc<Semaphore>,
+}
+
+impl Scheduler {
+    pub fn new(concurrency: Concurrency) -> Scheduler {
+        Scheduler {
+            courses: Arc::new(Semaphore::new(concurrency.max_courses.max(1))),
+            renders: Arc::new(Semaphore::new(concurrency.render_threads.max(1))),
+            concurrency,
+        }
+    }
+
+    /// Spawns a course's work, which starts once fewer than `max_courses` are running.
+    ///
+    /// The work gets its own handle on `api` limited to `max_requests_per_course` requests in flight.
+    pub fn spawn_course<T, F, Fut>(&self, api: &Api, work: F) -> JoinHandle<T>
+    where
+        F: FnOnce(Api) -> Fut + Send + 'static,
+        Fut: Future<Output = T> + Send,
+        T: Send + 'static,
+    {
+        let courses = self.courses.clone();
+        let api = self.course_api(api);
+        tokio::spawn(async move {
+            let _permit = courses.acquire_owned().await.expect("the course semaphore is never closed");
+            work(api).await
+        })
+    }
+
+    /// A handle on `api` for one course's requests, limited to `max_requests_per_course` in flight.
+    pub fn course_api(&self, api: &Api) -> Api {
+        api.with_course_limit(self.concurrency.max_requests_per_course)
+    }
+
+    /// Runs CPU-bound work (rendering a page) on the blocking pool, at most `render_threads` at a time,
+    /// so it never holds up the threads waiting on API responses. The time it takes counts as render time.
+    ///
+    /// The work isn't on a runtime thread, so Tera functions it calls may look things up with
+    /// `Handle::current().block_on(...)`, as `url_ok`, `getusername` and `link_card` do.
+    pub async fn render<T, F>(&self, work: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let _permit = self.renders.acquire().await.expect("the render semaphore is never closed");
+        let start_time = Instant::now();
+        match tokio::task::spawn_blocking(work).await {
+            Ok(value) => {
+                METRICS.record_render(start_time.elapsed());
+                value
+            }
+            Err(error) => std::panic::resume_unwind(error.into_panic()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Limits;
+    use crate::mock::MockServer;
+    use classroom1::hyper::{Body, Response};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Counts how many tracked sections run at once, remembering the most seen.
+    #[derive(Default)]
+    struct Gauge {
+        current: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    impl Gauge {
+        async fn track(&self) {
+            let now = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.current.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    async fn api() -> Api {
+        // The server is never contacted, requests below are stand-in futures
+        let server = MockServer::start().await;
+        Api::new(server.hub(), Limits { requests_per_second: 0.0, max_in_flight: 100, ..Default::default() })
+    }
+
+    #[tokio::test]
+    async fn limits_courses() {
+        let scheduler = Scheduler::new(Concurrency { max_courses: 2, ..Default::default() });
+        let api = api().await;
+        let gauge = Arc::new(Gauge::default());
+        let tasks: Vec<_> = (0..6).map(|_| {
+            let gauge = gauge.clone();
+            scheduler.spawn_course(&api, |_| async move { gauge.track().await })
+        }).collect();
+        futures::future::join_all(tasks).await;
+        assert_eq!(gauge.most.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn limits_requests_per_course() {
+        let scheduler = Scheduler::new(Concurrency { max_courses: 2, max_requests_per_course: 3, ..Default::default() });
+        let api = api().await;
+        let gauges: Vec<Arc<Gauge>> = (0..2).map(|_| Arc::new(Gauge::default())).collect();
+        let tasks: Vec<_> = gauges.iter().map(|gauge| {
+            let gauge = gauge.clone();
+            scheduler.spawn_course(&api, |api| async move {
+                let requests = (0..8).map(|_| api.call(|| async {
+                    gauge.track().await;
+                    Ok((Response::new(Body::empty()), ()))
+                }));
+                futures::future::join_all(requests).await;
+            })
+        }).collect();
+        futures::future::join_all(tasks).await;
+        // Each course is held to its own limit, one busy course doesn't take the other's share
+        for gauge in gauges {
+            assert_eq!(gauge.most.load(Ordering::SeqCst), 3);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn renders_off_the_runtime_threads() {
+        let scheduler = Scheduler::new(Concurrency { render_threads: 1, ..Default::default() });
+        let page = scheduler.render(|| {
+            // Only allowed outside the runtime's worker threads
+            tokio::runtime::Handle::current().block_on(async { "rendered".to_string() })
+        }).await;
+        assert_eq!(page, "rendered");
+    }
+}
@@ -0,0 +1,170 @@
+//! The OAuth scopes a run asks for, derived from the optional features it has turned on.
+//!
+//! Sign-in may grant fewer scopes than were asked for. Every call the hub makes asks the authenticator for its own
+//! default scope, and one that isn't covered by the stored token fails with a 403 or starts another sign-in halfway
+//! through the run. So once the token is in hand, features whose scopes were denied are turned off up front.
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+const SCOPE_PREFIX: &str = "https://www.googleapis.com/auth/";
+
+/// Needed for any page at all: course names, posts, coursework, materials and topics.
+const BASE_SCOPES: [&str; 5] = [
+    "classroom.courses.readonly",
+    "classroom.announcements.readonly",
+    "classroom.coursework.me.readonly",
+    "classroom.courseworkmaterials.readonly",
+    "classroom.topics.readonly",
+];
+
+const TOKEN_INFO_URL: &str = "https://oauth2.googleapis.com/tokeninfo";
+
+/// Parts of the archive that need more than the base scopes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+    /// Read access to Drive files attached to posts
+    Attachments,
+    /// Teachers, students and the names of post authors
+    Roster,
+    /// Students' submissions, for the answer tallies of taught courses
+    Submissions,
+    /// Teachers' profile photos
+    Photos,
+    /// Students' guardians and pending guardian invitations, on a page of their own for taught courses
+    Guardians,
+    /// Registering courses' roster and coursework feeds for push notifications, with `server.pubsub_topic` set
+    Notifications,
+}
+
+impl Feature {
+    fn scopes(self) -> &'static [&'static str] {
+        match self {
+            Feature::Attachments => &["drive.readonly"],
+            Feature::Roster => &["classroom.rosters.readonly", "classroom.profile.emails"],
+            Feature::Submissions => &["classroom.coursework.students.readonly"],
+            Feature::Photos => &["classroom.profile.photos"],
+            Feature::Guardians => &["classroom.guardianlinks.students.readonly"],
+            Feature::Notifications => &["classroom.push-notifications"],
+        }
+    }
+}
+
+/// The optional features of a run.
+#[derive(clap::Args, Clone, Debug)]
+pub struct Features {
+    /// Optional parts of the archive, each asking for its own scopes. Give the flag alone for course pages only.
+    #[arg(long, value_enum, value_delimiter = ',', num_args = 0.., default_values_t = Features::default().features)]
+    features: Vec<Feature>,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features { features: vec![Feature::Roster, Feature::Submissions, Feature::Photos] }
+    }
+}
+
+impl Features {
+    pub fn new(enabled: impl IntoIterator<Item = Feature>) -> Features {
+        Features { features: enabled.into_iter().collect() }
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    /// The full scope URLs to ask for: the base scopes, those of every enabled feature and `extra`.
+    pub fn scopes(&self, extra: &[String]) -> Vec<String> {
+        let mut scopes: Vec<String> = BASE_SCOPES.iter()
+            .chain(self.features.iter().flat_map(|feature| feature.scopes()))
+            .map(|scope| format!("{}{}", SCOPE_PREFIX, scope))
+            .collect();
+        for scope in extra {
+            if !scopes.contains(scope) {
+                scopes.push(scope.clone());
+            }
+        }
+        scopes
+    }
+
+    /// Turns off every feature missing one of its scopes from `granted`, with a warning naming what was denied.
+    ///
+    /// Missing base scopes are only warned about: those sections fail on their own and the rest of the page is kept.
+    pub fn negotiate(&mut self, granted: &HashSet<String>) {
+        let denied = |scope: &str| !granted.contains(&format!("{}{}", SCOPE_PREFIX, scope));
+        for scope in BASE_SCOPES.into_iter().filter(|scope| denied(scope)) {
+            tracing::warn!(scope, "Scope was not granted, its sections will fail");
+        }
+        self.features.retain(|feature| {
+            let missing: Vec<&str> = feature.scopes().iter().copied().filter(|scope| denied(scope)).collect();
+            if !missing.is_empty() {
+                tracing::warn!(feature = ?feature, scopes = ?missing, "Scope was not granted, skipping the feature");
+            }
+            missing.is_empty()
+        });
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenInfo {
+    scope: String,
+}
+
+/// The scopes an access token was actually granted, as Google reports them.
+pub async fn granted(access_token: &str) -> reqwest::Result<HashSet<String>> {
+    let url = reqwest::Url::parse_with_params(TOKEN_INFO_URL, [("access_token", access_token)]).expect("a valid URL");
+    let info: TokenInfo = reqwest::Client::new()
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(info.scope.split_whitespace().map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        features: Features,
+    }
+
+    fn granted(scopes: &[String], except: &str) -> HashSet<String> {
+        scopes.iter().filter(|scope| !scope.ends_with(except)).cloned().collect()
+    }
+
+    #[test]
+    fn scopes_follow_the_features() {
+        let names_only = Cli::parse_from(["csee", "--features"]).features;
+        assert_eq!(names_only.scopes(&[]).len(), BASE_SCOPES.len());
+
+        let scopes = Cli::parse_from(["csee"]).features.scopes(&[]);
+        assert!(scopes.contains(&format!("{}classroom.rosters.readonly", SCOPE_PREFIX)));
+        assert!(!scopes.iter().any(|scope| scope.contains("drive")), "attachments are off by default");
+
+        let features = Cli::parse_from(["csee", "--features", "attachments,photos"]).features;
+        assert!(features.has(Feature::Attachments) && !features.has(Feature::Roster));
+        let extra = format!("{}classroom.guardianlinks.students.readonly", SCOPE_PREFIX);
+        assert_eq!(features.scopes(&[extra.clone(), extra]).len(), BASE_SCOPES.len() + 3);
+    }
+
+    #[test]
+    fn denied_scopes_turn_their_features_off() {
+        let mut features = Features::new([Feature::Roster, Feature::Submissions, Feature::Photos]);
+        let scopes = features.scopes(&[]);
+        features.negotiate(&granted(&scopes, "profile.emails"));
+        assert!(!features.has(Feature::Roster));
+        assert!(features.has(Feature::Submissions) && features.has(Feature::Photos));
+
+        // A denied base scope doesn't cost a feature
+        features.negotiate(&granted(&scopes, "topics.readonly"));
+        assert!(features.has(Feature::Submissions) && features.has(Feature::Photos));
+    }
+}
@@ -0,0 +1,285 @@
+//! Log output for the binaries: a small `tracing` subscriber writing text or JSON lines to stderr.
+
+use classroom1::chrono::{SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::io::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// `2024-03-01T10:00:00.000Z  INFO course{id=1}: Pulled course data sections=...`
+    #[default]
+    Text,
+    /// One JSON object per line, with the enclosing spans and their fields
+    Json,
+}
+
+#[derive(Clone, Debug, clap::Args)]
+pub struct Logging {
+    /// Most detailed level logged: error, warn, info, debug or trace
+    #[arg(long, default_value = "info")]
+    pub log_level: Level,
+    #[arg(long, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+}
+
+impl Logging {
+    /// Installs the logger for the whole process.
+    pub fn init(&self) {
+        tracing::subscriber::set_global_default(Logger::new(self.log_level, self.log_format))
+            .expect("Logging was already set up");
+    }
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: Map<String, Value>,
+    parent: Option<Id>,
+    references: usize,
+}
+
+thread_local! {
+    // Spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+pub struct Logger {
+    level: Level,
+    format: LogFormat,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+impl Logger {
+    pub fn new(level: Level, format: LogFormat) -> Logger {
+        Logger { level, format, next_id: AtomicU64::new(1), spans: Mutex::new(HashMap::new()) }
+    }
+
+    fn current() -> Option<Id> {
+        ENTERED.with(|entered| entered.borrow().last().cloned())
+    }
+
+    /// `(name, fields)` of `span` and everything it's nested in, outermost first.
+    fn scope(&self, span: Option<Id>) -> Vec<(&'static str, Map<String, Value>)> {
+        let spans = self.spans.lock().unwrap();
+        let mut scope = Vec::new();
+        let mut next = span;
+        while let Some(data) = next.and_then(|id| spans.get(&id.into_u64())) {
+            scope.push((data.name, data.fields.clone()));
+            next = data.parent.clone();
+        }
+        scope.reverse();
+        scope
+    }
+
+    /// Formats one event as a line of output, without the trailing newline.
+    pub fn format(&self, event: &Event<'_>) -> String {
+        let mut fields = FieldMap::default();
+        event.record(&mut fields);
+        let mut fields = fields.0;
+        let message = match fields.remove("message") {
+            Some(Value::String(message)) => message,
+            Some(message) => message.to_string(),
+            None => String::new(),
+        };
+        let parent = if event.is_contextual() { Logger::current() } else { event.parent().cloned() };
+        let scope = self.scope(parent);
+        let metadata = event.metadata();
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        match self.format {
+            LogFormat::Json => json!({
+                "timestamp": timestamp,
+                "level": metadata.level().as_str(),
+                "target": metadata.target(),
+                "message": message,
+                "fields": fields,
+                "spans": scope.into_iter().map(|(name, fields)| json!({"name": name, "fields": fields})).collect::<Vec<_>>(),
+            }).to_string(),
+            LogFormat::Text => {
+                let mut line = format!("{} {:>5} ", timestamp, metadata.level().as_str());
+                for (name, fields) in &scope {
+                    line.push_str(name);
+                    if !fields.is_empty() {
+                        let _ = write!(line, "{{{}}}", text_fields(fields));
+                    }
+                    line.push(':');
+                }
+                if !scope.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(&message);
+                if !fields.is_empty() {
+                    let _ = write!(line, " {}", text_fields(&fields));
+                }
+                line
+            }
+        }
+    }
+}
+
+fn text_fields(fields: &Map<String, Value>) -> String {
+    fields.iter().map(|(key, value)| match value {
+        Value::String(text) if !text.contains(' ') => format!("{}={}", key, text),
+        value => format!("{}={}", key, value),
+    }).collect::<Vec<_>>().join(" ")
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        // More verbose levels compare greater
+        *metadata.level() <= self.level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::from_level(self.level))
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let id = Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut fields = FieldMap::default();
+        attributes.record(&mut fields);
+        let parent = if attributes.is_contextual() { Logger::current() } else { attributes.parent().cloned() };
+        let name = attributes.metadata().name();
+        self.spans.lock().unwrap().insert(id.into_u64(), SpanData { name, fields: fields.0, parent, references: 1 });
+        id
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            let mut fields = FieldMap(std::mem::take(&mut data.fields));
+            values.record(&mut fields);
+            data.fields = fields.0;
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let line = self.format(event);
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(index) = entered.iter().rposition(|id| id == span) {
+                entered.remove(index);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.references += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let closed = match spans.get_mut(&span.into_u64()) {
+            Some(data) => {
+                data.references -= 1;
+                data.references == 0
+            }
+            None => false,
+        };
+        if closed {
+            spans.remove(&span.into_u64());
+        }
+        closed
+    }
+}
+
+#[derive(Default)]
+struct FieldMap(Map<String, Value>);
+
+impl Visit for FieldMap {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Runs `log` under a logger that keeps its lines instead of printing them.
+    fn capture(format: LogFormat, log: impl FnOnce()) -> Vec<String> {
+        struct Capture(Logger, Arc<Mutex<Vec<String>>>);
+        impl Subscriber for Capture {
+            fn enabled(&self, metadata: &Metadata<'_>) -> bool { self.0.enabled(metadata) }
+            fn new_span(&self, attributes: &Attributes<'_>) -> Id { self.0.new_span(attributes) }
+            fn record(&self, span: &Id, values: &Record<'_>) { self.0.record(span, values) }
+            fn record_follows_from(&self, span: &Id, follows: &Id) { self.0.record_follows_from(span, follows) }
+            fn event(&self, event: &Event<'_>) { self.1.lock().unwrap().push(self.0.format(event)) }
+            fn enter(&self, span: &Id) { self.0.enter(span) }
+            fn exit(&self, span: &Id) { self.0.exit(span) }
+        }
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Capture(Logger::new(Level::INFO, format), lines.clone()), log);
+        let lines = lines.lock().unwrap().clone();
+        lines
+    }
+
+    #[test]
+    fn text_lines_show_the_span_path() {
+        let lines = capture(LogFormat::Text, || {
+            let _course = tracing::info_span!("course", id = "42").entered();
+            tracing::info!(sections = 5, "Pulled course data");
+            tracing::debug!("Not at this level");
+        });
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with(" INFO course{id=42}: Pulled course data sections=5"), "{}", lines[0]);
+    }
+
+    #[test]
+    fn json_lines_carry_fields_and_spans() {
+        let lines = capture(LogFormat::Json, || {
+            let _course = tracing::info_span!("course", id = "42").entered();
+            let _call = tracing::info_span!("api_call", attempt = 1).entered();
+            tracing::warn!(status = 429, "Retrying");
+        });
+        let line: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["message"], "Retrying");
+        assert_eq!(line["fields"]["status"], 429);
+        assert_eq!(line["spans"][0]["fields"]["id"], "42");
+        assert_eq!(line["spans"][1]["name"], "api_call");
+    }
+}
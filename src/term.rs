@@ -0,0 +1,183 @@
+use classroom1::api::Course;
+use regex::Regex;
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+use std::fmt;
+
+lazy_static::lazy_static! {
+    // "SEM 1 2022-2023", "(Sem 2 2022-23)", "Semester 1, 2023/24"
+    static ref SEMESTER: Regex = Regex::new(r"(?i)\bsem(?:ester)?\.?\s*([12])\s*,?\s*(\d{4})\s*[-/]\s*(\d{2}|\d{4})\b").unwrap();
+    // "Fall 2022", "Spring '23", "fall 22"
+    static ref SEASON: Regex = Regex::new(r"(?i)\b(fall|spring)\s*'?\s*(\d{4}|\d{2})\b").unwrap();
+    // "2022-23" on its own, used to filter by a whole academic year
+    static ref ACADEMIC_YEAR: Regex = Regex::new(r"^\s*(\d{4})\s*[-/]\s*(\d{2}|\d{4})\s*$").unwrap();
+}
+
+/// Half of an academic year. Fall is semester 1 and Spring is semester 2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Semester {
+    Fall,
+    Spring,
+}
+
+/// A school term parsed out of a course name or section, e.g. "SEM 1 2022-2023" is Fall of the 2022-23 year.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct Term {
+    pub semester: Semester,
+    /// Calendar year the academic year starts in, so Spring '23 has a `start_year` of 2022
+    pub start_year: i32,
+}
+
+impl Term {
+    /// Finds a term anywhere in `text`, returning `None` when nothing term-like is in it.
+    pub fn parse(text: &str) -> Option<Term> {
+        if let Some(caps) = SEMESTER.captures(text) {
+            let semester = if &caps[1] == "1" { Semester::Fall } else { Semester::Spring };
+            return Some(Term { semester, start_year: caps[2].parse().ok()? });
+        }
+        if let Some(caps) = SEASON.captures(text) {
+            let year = full_year(&caps[2])?;
+            return Some(if caps[1].eq_ignore_ascii_case("fall") {
+                Term { semester: Semester::Fall, start_year: year }
+            } else {
+                Term { semester: Semester::Spring, start_year: year - 1 }
+            });
+        }
+        None
+    }
+
+    /// The term of a course, taken from its name first and then its section.
+    pub fn of_course(course: &Course) -> Option<Term> {
+        course.name.as_deref().and_then(Term::parse)
+            .or_else(|| course.section.as_deref().and_then(Term::parse))
+    }
+
+    /// Calendar year the term takes place in
+    pub fn year(&self) -> i32 {
+        match self.semester {
+            Semester::Fall => self.start_year,
+            Semester::Spring => self.start_year + 1,
+        }
+    }
+}
+
+impl Ord for Term {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.start_year, self.semester).cmp(&(other.start_year, other.semester))
+    }
+}
+
+impl PartialOrd for Term {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Term {
+    /// Short label used on the index page, e.g. "Fall '22"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let season = match self.semester {
+            Semester::Fall => "Fall",
+            Semester::Spring => "Spring",
+        };
+        write!(f, "{} '{:02}", season, self.year() % 100)
+    }
+}
+
+fn full_year(year: &str) -> Option<i32> {
+    let year: i32 = year.parse().ok()?;
+    Some(if year < 100 { 2000 + year } else { year })
+}
+
+/// What `--term` selects: a single semester ("Fall 2022", "Sem 2 2022-23") or a whole academic year ("2022-23").
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TermSelector {
+    Term(Term),
+    AcademicYear(i32),
+}
+
+impl TermSelector {
+    pub fn parse(text: &str) -> Option<TermSelector> {
+        if let Some(term) = Term::parse(text) {
+            return Some(TermSelector::Term(term));
+        }
+        let caps = ACADEMIC_YEAR.captures(text)?;
+        Some(TermSelector::AcademicYear(caps[1].parse().ok()?))
+    }
+
+    pub fn matches(&self, term: Option<Term>) -> bool {
+        match (self, term) {
+            (TermSelector::Term(wanted), Some(term)) => *wanted == term,
+            (TermSelector::AcademicYear(year), Some(term)) => *year == term.start_year,
+            (_, None) => false,
+        }
+    }
+}
+
+/// Courses of one term, in the order they are shown on the index page
+#[derive(Clone, Debug, Serialize)]
+pub struct TermGroup {
+    pub label: String,
+    pub term: Option<Term>,
+    pub courses: Vec<Course>,
+}
+
+/// Groups courses by parsed term, newest term first, with courses that have no term last under "Other".
+pub fn group_by_term(courses: &[Course]) -> Vec<TermGroup> {
+    let mut groups: Vec<TermGroup> = Vec::new();
+    for course in courses {
+        let term = Term::of_course(course);
+        match groups.iter_mut().find(|group| group.term == term) {
+            Some(group) => group.courses.push(course.clone()),
+            None => groups.push(TermGroup {
+                label: term.map_or_else(|| "Other".to_string(), |term| term.to_string()),
+                term,
+                courses: vec![course.clone()],
+            }),
+        }
+    }
+    // None sorts before Some, so reversing puts the newest term first and "Other" last
+    groups.sort_by_key(|group| Reverse(group.term));
+    for group in &mut groups {
+        group.courses.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn course(name: &str, section: &str) -> Course {
+        Course { name: Some(name.to_string()), section: Some(section.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn terms_are_parsed() {
+        assert_eq!(Term::parse("Biology SEM 1 2022-2023"), Some(Term { semester: Semester::Fall, start_year: 2022 }));
+        assert_eq!(Term::parse("Chemistry (Spring '23)"), Some(Term { semester: Semester::Spring, start_year: 2022 }));
+        assert_eq!(Term::parse("Sem 2 2022-23").map(|term| term.to_string()).as_deref(), Some("Spring '23"));
+        // A bare academic year is no one term, but selects both
+        assert_eq!(Term::parse("2022-23"), None);
+        assert_eq!(TermSelector::parse("2022-23"), Some(TermSelector::AcademicYear(2022)));
+        assert_eq!(Term::parse("Homeroom"), None);
+        assert_eq!(TermSelector::parse("Homeroom"), None);
+    }
+
+    #[test]
+    fn newest_terms_come_first_and_other_last() {
+        let courses = [
+            course("Physics", "Period 3"),
+            course("Biology", "Fall 2022"),
+            course("Art Spring '23", ""),
+            course("Algebra", "SEM 1 2022-2023"),
+            course("Homeroom", ""),
+        ];
+        let groups = group_by_term(&courses);
+        let labels: Vec<&str> = groups.iter().map(|group| group.label.as_str()).collect();
+        assert_eq!(labels, ["Spring '23", "Fall '22", "Other"]);
+        let fall: Vec<&str> = groups[1].courses.iter().map(|course| course.name.as_deref().unwrap()).collect();
+        assert_eq!(fall, ["Algebra", "Biology"]);
+        assert_eq!(groups[2].courses.len(), 2);
+    }
+}
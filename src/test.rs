@@ -1,39 +1,38 @@
 extern crate google_classroom1 as classroom1;
 use actix_rt::spawn;
-use actix_web::{web, App, HttpResponse, HttpServer, Result, ResponseError};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Result, ResponseError};
 use chrono::{Datelike, Timelike};
-use classroom1::oauth2::{InstalledFlowAuthenticator, InstalledFlowReturnMethod, ApplicationSecret};
-use hyper::{Response, Body};
 use oauth2::basic::{BasicClient, BasicTokenType};
 use oauth2::reqwest::http_client;
 use oauth2::{
     AuthorizationCode, AuthUrl, ClientId, ClientSecret, CsrfToken, TokenUrl, RedirectUrl, TokenResponse, EmptyExtraTokenFields, AccessToken, RefreshToken, Scope, StandardTokenResponse,
 };
+use clap::Parser;
+use csee::api::Limits;
+use csee::archive::{self, assigned_courses, connect, invitations, page_groups, templates, Account, Site};
+use csee::cache::CacheOptions;
+use csee::config::{AuthConfig, Config};
+use csee::daemon::RunLock;
+use csee::embeds::EmbedOptions;
+use csee::filter::{list_courses, CourseFilter, Role};
+use csee::index::{index_context, redact_index, CourseStats, STATS_FILE};
+use csee::invitations::INVITATIONS_PAGE;
+use csee::metrics::METRICS;
+use csee::notifications::{register, FeedKind, Notification, Refreshes};
+use csee::redact::Redaction;
+use csee::roster::StudentPrivacy;
+use csee::schedule::{Concurrency, Scheduler};
+use csee::scopes::{self, Feature, Features};
+use csee::telemetry::Logging;
+use csee::theme::{Theme, ThemeOptions};
 use serde::{Deserialize, Serialize};
-use tera::Tera;
 use std::fs::File;
 use std::io::{self, Read, Write};
-use std::time::Instant;
-use classroom1::api::{ListCoursesResponse, Announcement, CourseWork, CourseWorkMaterial, Teacher, Topic};
-use classroom1::{Classroom, hyper, hyper_rustls, chrono};
-use serde_json::Value;
-use tera::Context;
-use tokio::task;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use classroom1::api::Course;
+use classroom1::chrono;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex, mpsc};
-use tokio::runtime::{Builder, Runtime};
-
-#[derive(Clone)]
-struct CourseContent {
-    id: Option<String>,
-    course_announcements: Option<Vec<Announcement>>,
-    course_work: Option<Vec<CourseWork>>,
-    course_materials: Option<Vec<CourseWorkMaterial>>,
-    name: Option<String>,
-    teachers: Option<Vec<Teacher>>,
-    topics: Option<Vec<Topic>>,
-    tera: Tera,
-}
 
 
 // AuthCallbackParams struct for deserialization of query parameters
@@ -55,29 +54,26 @@ struct Installed {
     client_secret: String,
 }
 
-lazy_static::lazy_static! {
-    static ref CLIENT: BasicClient = {
-        // Read credentials from the JSON file
-        let credentials = get_credentials().expect("Failed to read credentials from JSON");
-
-        // Google OAuth2 credentials
-        let client_id = ClientId::new(credentials.installed.client_id);
-        let client_secret = ClientSecret::new(credentials.installed.client_secret);
-        let auth_url = AuthUrl::new("https://accounts.google.com/o/oauth2/auth".to_string())
-            .expect("Failed to parse Auth URL");
-        let token_url = TokenUrl::new("https://oauth2.googleapis.com/token".to_string())
-            .expect("Failed to parse Token URL");
-        let redirect_url =
-            RedirectUrl::new("http://localhost:8080/auth/callback".to_string())
-                .expect("Failed to parse Redirect URL");
-        // Create an OAuth2 client
-        BasicClient::new(client_id, Some(client_secret), auth_url, Some(token_url))
-            .set_redirect_uri(redirect_url)
-    };
+fn oauth_client(auth: &AuthConfig) -> io::Result<BasicClient> {
+    // Read credentials from the JSON file
+    let credentials = get_credentials(&auth.credentials)?;
+
+    // Google OAuth2 credentials
+    let client_id = ClientId::new(credentials.installed.client_id);
+    let client_secret = ClientSecret::new(credentials.installed.client_secret);
+    let auth_url = AuthUrl::new("https://accounts.google.com/o/oauth2/auth".to_string())
+        .expect("Failed to parse Auth URL");
+    let token_url = TokenUrl::new("https://oauth2.googleapis.com/token".to_string())
+        .expect("Failed to parse Token URL");
+    // Already checked when the configuration was loaded
+    let redirect_url = RedirectUrl::new(auth.redirect_url.clone()).expect("Failed to parse Redirect URL");
+    // Create an OAuth2 client
+    Ok(BasicClient::new(client_id, Some(client_secret), auth_url, Some(token_url))
+        .set_redirect_uri(redirect_url))
 }
 
-fn get_credentials() -> io::Result<Credentials> {
-    let mut file = File::open("credentials.json")?;
+fn get_credentials(path: &Path) -> io::Result<Credentials> {
+    let mut file = File::open(path)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let credentials: Credentials = serde_json::from_str(&contents)?;
@@ -96,32 +92,133 @@ impl std::fmt::Display for MyError {
 
 impl ResponseError for MyError {}
 
+/// Serve the Google sign-in flow on 127.0.0.1:8080 and archive every course once signed in
+#[derive(Parser)]
+struct Cli {
+    /// Whether to archive each course's student roster, and how much of it
+    #[arg(long, value_enum, default_value = "omit")]
+    students: StudentPrivacy,
+    /// TOML file with the privacy redaction rules applied before anything is rendered
+    #[arg(long)]
+    redaction: Option<PathBuf>,
+    /// Merge courses that share an alias, or a `merge` name in the configuration, into one page
+    #[arg(long)]
+    merge_sections: bool,
+    #[command(flatten)]
+    embeds: EmbedOptions,
+    #[command(flatten)]
+    features: Features,
+    #[command(flatten)]
+    theme: ThemeOptions,
+    #[command(flatten)]
+    limits: Limits,
+    #[command(flatten)]
+    concurrency: Concurrency,
+    #[command(flatten)]
+    cache: CacheOptions,
+    #[command(flatten)]
+    logging: Logging,
+}
+
+/// How pages are archived, the same for every request.
+#[derive(Clone)]
+struct Settings {
+    features: Features,
+    students: StudentPrivacy,
+    redaction: Redaction,
+    merge_sections: bool,
+    embeds: EmbedOptions,
+    limits: Limits,
+    concurrency: Concurrency,
+    cache: CacheOptions,
+}
+
+impl Settings {
+    /// Signs in every configured account in turn.
+    async fn sign_in(&self, config: &Config) -> Result<Vec<Account>, MyError> {
+        let mut accounts = Vec::new();
+        for account in config.accounts() {
+            let signed_in = connect(config, &account, &self.features, &self.limits, &self.cache)
+                .await
+                .map_err(|error| MyError(format!("Failed to sign in {}: {}", account.name, error)))?;
+            accounts.push(signed_in);
+        }
+        Ok(accounts)
+    }
+
+    /// The site the pages are written into, as `html` writes it.
+    fn site(&self, theme: Theme, config: &Config, accounts: Vec<Account>) -> Result<Site, MyError> {
+        let teras = templates(&theme, &accounts, &self.cache, &self.embeds)
+            .map_err(|error| MyError(format!("Failed to load the templates: {}", error)))?;
+        Ok(Site {
+            accounts,
+            teras,
+            theme,
+            redaction: self.redaction.clone(),
+            output: config.render.output.clone(),
+            students: self.students,
+            embeds: self.embeds.clone(),
+            cache: self.cache.clone(),
+        })
+    }
+}
+
 #[actix_rt::main]
 async fn main() -> io::Result<()> {
-    HttpServer::new(|| {
+    let (cli, config) = csee::config::parse::<Cli>();
+    cli.logging.init();
+    let theme = cli.theme.load()?;
+    let client = oauth_client(&config.auth)?;
+    let bind = config.server.bind.clone();
+    let settings = Settings {
+        features: cli.features,
+        students: cli.students,
+        redaction: match &cli.redaction {
+            Some(path) => Redaction::load(path)?,
+            None => Redaction::default(),
+        },
+        merge_sections: cli.merge_sections,
+        embeds: cli.embeds,
+        limits: cli.limits,
+        concurrency: cli.concurrency,
+        cache: cli.cache,
+    };
+    // Shared by every worker, so deliveries for one course coalesce whichever worker takes them
+    let refreshes = web::Data::new(Refreshes::default());
+    HttpServer::new(move || {
         App::new()
+            .app_data(web::Data::new(theme.clone()))
+            .app_data(web::Data::new(client.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(settings.clone()))
             .route("/login", web::get().to(login))
             .route("/auth/callback", web::get().to(auth_callback))
+            .route("/metrics", web::get().to(metrics))
+            .route("/invitations/refresh", web::post().to(refresh_invitations))
+            .route("/notifications", web::post().to(receive_notification))
+            .route("/notifications/register", web::post().to(register_notifications))
+            .app_data(refreshes.clone())
     })
-    .bind("127.0.0.1:8080")?
+    .bind(bind)?
     .run()
     .await
 }
 
-async fn login() -> HttpResponse {
+async fn metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(METRICS.prometheus())
+}
+
+async fn login(client: web::Data<BasicClient>, config: web::Data<Config>, settings: web::Data<Settings>) -> HttpResponse {
+    // Sign-in here only saves `auth.tokens`, which configured accounts don't use
+    if config.accounts.len() > 1 {
+        return HttpResponse::Conflict().body("Several accounts are configured, sign each in by running html once");
+    }
     // Redirect the user to the Google OAuth2 authorization URL
-    let (auth_url, _csrf_state) = CLIENT
+    let (auth_url, _csrf_state) = client
         .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("https://www.googleapis.com/auth/drive.readonly".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/classroom.announcements.readonly".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/classroom.courses.readonly".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/classroom.coursework.students.readonly".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/classroom.coursework.me.readonly".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/classroom.courseworkmaterials.readonly".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/classroom.rosters.readonly".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/classroom.profile.emails".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/classroom.profile.photos".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/classroom.topics.readonly".to_string()))
+        .add_scopes(settings.features.scopes(&config.auth.scopes).into_iter().map(Scope::new))
         .url();
     
     HttpResponse::Found()
@@ -129,179 +226,269 @@ async fn login() -> HttpResponse {
         .finish()
 }
 
-async fn fetch_classroom_data(auth_secret: ApplicationSecret) -> Result<(), MyError> {
-    // Create the OAuth2 authenticator using the ApplicationSecret
-    let auth = InstalledFlowAuthenticator::builder(auth_secret, InstalledFlowReturnMethod::HTTPRedirect)
-        .persist_tokens_to_disk("tokens.json")
-        .build()
-        .await
-        .expect("Failed to build InstalledFlowAuthenticator");
+/// Rewrites invitations.html with every account's invitations, linking the course pages already archived. Returns
+/// how many invitations it lists, `None` when no account may see them.
+async fn write_invitations(site: &Site, courses: &[Course], owners: &[usize]) -> Result<Option<usize>, MyError> {
+    let mut taught = Vec::new();
+    for (index, account) in site.accounts.iter().enumerate() {
+        if !account.features.has(Feature::Roster) {
+            taught.push(Vec::new());
+            continue;
+        }
+        let teaching = list_courses(&account.api, &CourseFilter { role: Some(Role::Teacher), ..Default::default() })
+            .await
+            .map_err(|error| MyError(format!("Failed to list the courses {} teaches: {}", account.name, error)))?;
+        // Only those it archives, so a course two accounts teach is listed once
+        let ids: Vec<String> = teaching.into_iter()
+            .filter_map(|course| course.id)
+            .filter(|id| courses.iter().zip(owners).any(|(course, &owner)| owner == index && course.id.as_ref() == Some(id)))
+            .collect();
+        taught.push(ids);
+    }
+    let Some(invitations) = invitations(&site.accounts, courses, &taught, &site.output).await else {
+        return Ok(None);
+    };
+    invitations.write(&site.teras[0], &site.theme, &site.output)?;
+    Ok(Some(invitations.mine.len() + invitations.outstanding.len()))
+}
 
-    let hub = Classroom::new(hyper::Client::builder().build(hyper_rustls::HttpsConnectorBuilder::new().with_native_roots().unwrap().https_or_http().enable_http1().build()), auth);
-    let courses = hub.courses();
-    let response: (Response<Body>, ListCoursesResponse) = courses.list().page_size(100).doit().await.unwrap();
+/// Refreshes the invitations page on its own, without archiving the courses again.
+async fn refresh_invitations(
+    request: HttpRequest,
+    params: web::Query<NotificationParams>,
+    theme: web::Data<Theme>,
+    config: web::Data<Config>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, MyError> {
+    if !trusted(&request, &params, &config) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    if !settings.features.has(Feature::Roster) {
+        return Ok(HttpResponse::NotFound().body("Invitations need the roster feature"));
+    }
+    let accounts = settings.sign_in(&config).await?;
+    let (courses, owners) = assigned_courses(&config, &accounts, &CourseFilter::default())
+        .await
+        .map_err(|error| MyError(format!("Failed to list courses: {}", error)))?;
+    // Answered straight away, so a run that's writing the archive isn't waited for
+    let _lock = match RunLock::acquire(&config.lock_file()) {
+        Ok(lock) => lock,
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => return Ok(HttpResponse::Conflict().body(error.to_string())),
+        Err(error) => return Err(error.into()),
+    };
+    let site = settings.site(theme.get_ref().clone(), &config, accounts)?;
+    let Some(count) = write_invitations(&site, &courses, &owners).await? else {
+        return Ok(HttpResponse::NotFound().body("No account was granted the roster"));
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body(format!("Wrote {} with {} pending invitations", INVITATIONS_PAGE, count)))
+}
 
-    let mut tera = Tera::new("../templates/**/*.html").unwrap();
-    let mut context = Context::new();
-    let mut buffer = Vec::new();
-    let course_list = response.1.courses.clone().unwrap();
-    tera.add_template_file("templates/courses.html", Some("course_list")).unwrap();
-    tera.add_template_file("templates/course.html", Some("course")).unwrap();
-    context.insert("courses", &course_list);
-    tera.render_to("course_list", &context, &mut buffer).unwrap();
-    let mut file = File::create("html/courses.html").expect("Failed to create file");
-
-    let hub_arc = Arc::new(Mutex::new(hub.clone()));
-
-    tera.register_function("getusername", move |args: &HashMap<String, Value>| {
-        if let Some(id) = args.get("id").and_then(|v| v.as_str()) {
-            let hub_mutex = &hub_arc.lock().unwrap(); // Acquire the lock to access hub
-    
-            let user_profile = task::block_in_place(|| {
-                let runtime = Builder::new_current_thread()
-                    .enable_all()
-                    .build()
-                    .unwrap();
-                runtime.block_on(async move {
-                    hub_mutex.user_profiles().get(id).doit().await
-                })
-            });
-            match user_profile {
-                Ok(profile) => {
-                    let name = profile.1.name.unwrap().full_name.unwrap_or_else(|| "None".to_string());
-                    let name_value: Value = Value::String(name);
-                    Ok(name_value)
+/// Registers the roster and coursework feeds of each course for push notifications, with the account that archives
+/// it. Returns how many took.
+async fn register_feeds(config: &Config, accounts: &[Account], courses: &[Course], owners: &[usize]) -> usize {
+    let Some(topic) = &config.server.pubsub_topic else {
+        return 0;
+    };
+    let mut registered = 0;
+    for (course, &owner) in courses.iter().zip(owners) {
+        let (Some(course_id), account) = (course.id.as_deref(), &accounts[owner]) else {
+            continue;
+        };
+        if !account.features.has(Feature::Notifications) {
+            continue;
+        }
+        // Roster notifications are only sent to those who can read the roster
+        let kinds: &[FeedKind] = if account.features.has(Feature::Roster) { &[FeedKind::Roster, FeedKind::CourseWork] } else { &[FeedKind::CourseWork] };
+        for &kind in kinds {
+            match register(&account.api, course_id, topic, kind).await {
+                Ok(registration) => {
+                    tracing::info!(course_id, feed = kind.feed_type(), expires = ?registration.expiry_time, "Registered for notifications");
+                    registered += 1;
                 }
-                Err(_) => {
-                    let name = "None".to_string();
-                    let name_value: Value = Value::String(name);
-                    Ok(name_value)
-                },
+                Err(error) => tracing::warn!(course_id, feed = kind.feed_type(), error = %error, "Failed to register for notifications"),
             }
-        } else {
-            Err(tera::Error::msg("No 'id' argument provided"))
         }
-    });
+    }
+    registered
+}
 
-    async fn check_url(url: String) -> bool {
-        match reqwest::get(&url).await {
-            Ok(response) => response.status() == reqwest::StatusCode::OK,
-            Err(_) => false,
-        }
+/// Whether a request may make the server call Classroom: it carries `server.notification_token` as `?token=` when
+/// one is set, and comes from this machine when not.
+fn trusted(request: &HttpRequest, params: &NotificationParams, config: &Config) -> bool {
+    match &config.server.notification_token {
+        Some(token) => params.token.as_ref() == Some(token),
+        None => request.peer_addr().is_some_and(|peer| peer.ip().is_loopback()),
     }
-    
-    // Register the Tera filter function
-    tera.register_function("url_ok", move |args: &HashMap<String, Value>| {
-        if let Some(url) = args.get("url").and_then(|v| v.as_str()) {
-            let url = url.to_string(); // Clone the URL for async closure
-    
-            // Create a channel for communication between threads
-            let (sender, receiver) = mpsc::channel();
-    
-            // Spawn a new thread to run the async block
-            std::thread::spawn(move || {
-                let runtime = Runtime::new().unwrap();
-                let result = runtime.block_on(async move {
-                    check_url(url).await
-                });
-                let _ = sender.send(result);
-            });
-    
-            // Wait for the result from the spawned thread
-            let result = receiver.recv().unwrap();
-    
-            Ok(Value::Bool(result))
-        } else {
-            Err(tera::Error::msg("No 'url' argument provided"))
-        }
-    });
+}
 
-    /*tera.register_function("to_utc", move |args: &HashMap<String, Value>| {
-        if let Some(date) = args.get("date") {
-            let utc_date = "".to_string();
-            match date.year {
-                Some(year) => date_due.push_str(&year.to_string()),
-                None => Err(tera::Error::msg("No 'date.month' argument"))
-            }
-            date_due.push_str("-");
-            match date.month {
-                Some(month) => date_due.push_str(&month.to_string()),
-                None => Err(tera::Error::msg("No 'date.month' argument"))
+/// Registers every archived course's feeds again. Registrations last a week, so this wants calling about as often.
+async fn register_notifications(
+    request: HttpRequest,
+    params: web::Query<NotificationParams>,
+    config: web::Data<Config>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, MyError> {
+    if !trusted(&request, &params, &config) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    if !settings.features.has(Feature::Notifications) || config.server.pubsub_topic.is_none() {
+        return Ok(HttpResponse::NotFound().body("Notifications need the notifications feature and server.pubsub_topic"));
+    }
+    let accounts = settings.sign_in(&config).await?;
+    let (courses, owners) = assigned_courses(&config, &accounts, &CourseFilter::default())
+        .await
+        .map_err(|error| MyError(format!("Failed to list courses: {}", error)))?;
+    let registered = register_feeds(&config, &accounts, &courses, &owners).await;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body(format!("Registered {} feeds of {} courses", registered, courses.len())))
+}
+
+#[derive(Deserialize)]
+struct NotificationParams {
+    token: Option<String>,
+}
+
+/// Takes a push delivery, from Pub/Sub or the `notify` simulator, and re-archives the course it's about.
+///
+/// The delivery is acknowledged straight away, since Pub/Sub sends it again if the answer is slow. Deliveries for a
+/// course that is already being refreshed are folded into one more refresh once it's done.
+async fn receive_notification(
+    params: web::Query<NotificationParams>,
+    body: web::Bytes,
+    theme: web::Data<Theme>,
+    config: web::Data<Config>,
+    settings: web::Data<Settings>,
+    refreshes: web::Data<Refreshes>,
+) -> HttpResponse {
+    // Always set with a Pub/Sub topic; without one only the `notify` simulator delivers, and may leave it off
+    if params.token != config.server.notification_token {
+        return HttpResponse::Forbidden().finish();
+    }
+    let notification = match Notification::from_delivery(&body) {
+        Ok(notification) => notification,
+        Err(error) => return HttpResponse::BadRequest().body(error.to_string()),
+    };
+    let course_id = notification.resource_id.course_id;
+    tracing::info!(course_id, collection = notification.collection, event = notification.event_type, "Received a notification");
+    if config.course(&course_id).skip {
+        return HttpResponse::NoContent().finish();
+    }
+    if refreshes.begin(&course_id) {
+        let (theme, config, settings) = (theme.get_ref().clone(), config.get_ref().clone(), settings.get_ref().clone());
+        let refreshes = refreshes.into_inner();
+        let id = course_id.clone();
+        spawn(async move {
+            loop {
+                if let Err(error) = refresh_course(&id, &theme, &config, &settings).await {
+                    tracing::warn!(course_id = id, error = %error, "Failed to refresh the course");
+                }
+                if !refreshes.finish(&id) {
+                    break;
+                }
             }
-            date_due.push_str("-");
-            match date.day {
-                Some(day) => date_due.push_str(&day.to_string()),
-                None => Err(tera::Error::msg("No 'date.day' argument"))
+        });
+    }
+    HttpResponse::Accepted().content_type("text/plain").body(format!("Refreshing course {}", course_id))
+}
+
+/// How long a refresh waits before trying the run lock again.
+const LOCK_RETRY: Duration = Duration::from_secs(5);
+
+/// Takes the run lock, waiting for a run of `html` or another refresh to be done with the archive first.
+async fn queue_for_lock(config: &Config) -> io::Result<RunLock> {
+    loop {
+        match RunLock::acquire(&config.lock_file()) {
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                tracing::info!(error = %error, "Waiting for the archive to be written");
+                tokio::time::sleep(LOCK_RETRY).await;
             }
-    
-            Ok(Value::Bool(result))
-        } else {
-            Err(tera::Error::msg("No 'date or time' argument provided"))
+            result => return result,
         }
-    });*/
-
-    file.write_all(&buffer).expect("Failed to write to file");
-    let mut reqquery_vec: Vec<CourseContent> = Vec::new();
-
-    for course in response.1.courses.unwrap() {
-        println!("Pulling Data From {}", course.name.clone().unwrap());
-        let start_time = Instant::now();
-        let the_id = course.clone().id.unwrap();
-        let course_content = CourseContent {
-            id: Some(course.clone().id.unwrap()),
-            course_announcements: Some(courses.announcements_list(&the_id).doit().await.unwrap().1.announcements.clone().unwrap_or_default()),
-            course_work: Some(courses.course_work_list(&the_id).doit().await.unwrap().1.course_work.clone().unwrap_or_default()),
-            course_materials: Some(courses.course_work_materials_list(&the_id).doit().await.unwrap().1.course_work_material.clone().unwrap_or_default()),
-            name: Some(course.name.clone().unwrap_or_default()),
-            teachers: Some(courses.teachers_list(&the_id).doit().await.unwrap().1.teachers.clone().unwrap_or_default()),
-            topics: Some(courses.topics_list(&the_id).doit().await.unwrap().1.topic.clone().unwrap_or_default()),
-            tera: tera.clone(),
-        };
-        reqquery_vec.push(course_content);
-        println!(
-            "Took {:?}",
-            start_time.elapsed(),
-        );
     }
+}
 
-    let tasks: Vec<_> = reqquery_vec.clone().iter().map(|course| {
-        let course = course.clone();
-        tokio::spawn(async move {
-            let start_time = Instant::now();
-            let mut buffer = Vec::new();
-            let mut context = Context::new();
-            context.insert("name", &course.name.clone().unwrap());
-            if course.course_announcements.is_some() {
-                context.insert("course_announcements", &course.course_announcements.clone().unwrap());
-            }
-            if course.course_work.is_some() {
-                context.insert("coursework", &course.course_work.clone().unwrap());
-            }
-            if course.course_materials.is_some() {
-                context.insert("course_materials", &course.course_materials.clone().unwrap());
-            }
-            if course.teachers.is_some() {
-                context.insert("teachers", &course.teachers.clone().unwrap());
-            }
-            if course.topics.is_some() {
-                context.insert("topics", &course.topics.clone().unwrap());
-            }
-            //let course_work_student_submission_list: (Response<Body>, ListStudentSubmissionsResponse) = courses.course_work_student_submissions_list(course_id: &the_id).doit().await.unwrap();
-            //println!("{:#?}", &context);
-            course.tera.render_to("course", &context, &mut buffer).unwrap();
-            let mut file = File::create(format!("html/courses/{}.html", course.clone().id.unwrap())).expect("Failed to create file");
-            file.write_all(&buffer).expect("Failed to write to file");
-            let end_time = Instant::now();
-            let iteration_duration = end_time - start_time;
-            println!("Course: {}, {}\nRender Time: {:?}", course.name.clone().unwrap(), course.clone().id.unwrap(), iteration_duration);
-        })
-    }).collect();
-    futures::future::join_all(tasks).await;
+/// Fetches the page a course is on again and rewrites it, as `html` would.
+async fn refresh_course(course_id: &str, theme: &Theme, config: &Config, settings: &Settings) -> Result<(), MyError> {
+    let accounts = settings.sign_in(config).await?;
+    let (course_list, owners) = assigned_courses(config, &accounts, &CourseFilter::default())
+        .await
+        .map_err(|error| MyError(format!("Failed to list courses: {}", error)))?;
+    // The course may be a section merged into another's page
+    let groups = page_groups(config, &accounts, &course_list, &owners, settings.merge_sections).await;
+    let group = groups.iter()
+        .find(|group| group.iter().any(|&index| course_list[index].id.as_deref() == Some(course_id)))
+        .ok_or_else(|| MyError(format!("Course {} is not archived", course_id)))?;
+    let _lock = queue_for_lock(config).await?;
+    std::fs::create_dir_all(config.render.output.join("courses"))?;
+    let site = settings.site(theme.clone(), config, accounts)?;
+    let page = site.page(config, &course_list, &owners, group);
+    let scheduler = Scheduler::new(settings.concurrency.clone());
+    scheduler.clone().spawn_course(&page.account.api.clone(), move |api| async move { page.archive(api, &scheduler).await })
+        .await
+        .map_err(|error| MyError(format!("Failed to refresh course {}: {}", course_id, error)))??;
     Ok(())
 }
 
+async fn fetch_classroom_data(theme: Theme, config: Config, settings: Settings) -> Result<(), MyError> {
+    let run_time = Instant::now();
+    let accounts = settings.sign_in(&config).await?;
+    let (course_list, owners) = assigned_courses(&config, &accounts, &CourseFilter::default())
+        .await
+        .map_err(|error| MyError(format!("Failed to list courses: {}", error)))?;
+
+    let _lock = queue_for_lock(&config).await?;
+    std::fs::create_dir_all(config.render.output.join("courses"))?;
+    theme.copy_static(&config.render.output).map_err(|error| MyError(format!("Failed to copy the theme's assets: {}", error)))?;
+    let groups = page_groups(&config, &accounts, &course_list, &owners, settings.merge_sections).await;
+    let site = settings.site(theme, &config, accounts)?;
+    let scheduler = Scheduler::new(settings.concurrency.clone());
+    let tasks: Vec<_> = groups.iter().map(|group| {
+        let page = site.page(&config, &course_list, &owners, group);
+        let scheduler = scheduler.clone();
+        scheduler.clone().spawn_course(&page.account.api.clone(), move |api| async move { page.archive(api, &scheduler).await })
+    }).collect();
+    let mut stats: HashMap<String, CourseStats> = HashMap::new();
+    for result in futures::future::join_all(tasks).await {
+        match result {
+            Ok(Ok(archived)) => {
+                stats.insert(archived.id, archived.stats);
+            }
+            Ok(Err(error)) => tracing::error!(error = %error, "Failed to archive a course"),
+            Err(error) => tracing::error!(error = %error, "Course task failed"),
+        }
+    }
 
-async fn auth_callback(params: web::Query<AuthCallbackParams>) -> Result<HttpResponse, MyError> {
+    let invitations_page = write_invitations(&site, &course_list, &owners).await?.is_some();
+    // Listed with what was pulled for each course, merged sections once
+    let mut pages: Vec<Course> = groups.iter().map(|group| course_list[group[0]].clone()).collect();
+    redact_index(&mut pages, &mut stats, &site.redaction);
+    let mut context = index_context(&pages, &stats);
+    if invitations_page {
+        context.insert("invitations_page", INVITATIONS_PAGE);
+    }
+    let mut buffer = Vec::new();
+    site.theme.insert(&mut context, "");
+    site.teras[0].render_to("courses.html", &context, &mut buffer).map_err(archive::Error::from)?;
+    File::create(config.render.output.join("courses.html"))?.write_all(&buffer)?;
+    serde_json::to_writer_pretty(File::create(config.render.output.join(STATS_FILE))?, &stats).map_err(archive::Error::from)?;
+    register_feeds(&config, &site.accounts, &course_list, &owners).await;
+    METRICS.log_summary(run_time.elapsed());
+    Ok(())
+}
+
+async fn auth_callback(
+    params: web::Query<AuthCallbackParams>,
+    theme: web::Data<Theme>,
+    client: web::Data<BasicClient>,
+    config: web::Data<Config>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, MyError> {
+    if config.accounts.len() > 1 {
+        return Ok(HttpResponse::Conflict().body("Several accounts are configured, sign each in by running html once"));
+    }
     // Verify the CSRF token (in a real app, you should use a proper session store)
     let csrf_state = CsrfToken::new(params.state.clone());
     let csrf_secret = csrf_state.secret();
@@ -315,22 +502,32 @@ async fn auth_callback(params: web::Query<AuthCallbackParams>) -> Result<HttpRes
 
     // Use actix_web::block to run the blocking code asynchronously
     let token_response = actix_web::web::block(move || {
-        CLIENT.exchange_code(code).request(http_client)
+        client.exchange_code(code).request(http_client)
     })
     .await
     .map_err(|error| MyError(format!("Failed to exchange code for access token: {}", error)))?
     .unwrap();
 
-    let auth_secret = classroom1::oauth2::read_application_secret("credentials.json")
-        .await
-        .expect("client secret couldn't be read.");
+    // Skip what sign-in didn't allow now, rather than on a 403 halfway through
+    let mut settings = settings.get_ref().clone();
+    let features = &mut settings.features;
+    match token_response.scopes() {
+        Some(granted) => features.negotiate(&granted.iter().map(|scope| scope.as_str().to_owned()).collect()),
+        None => match scopes::granted(token_response.access_token().secret()).await {
+            Ok(granted) => features.negotiate(&granted),
+            Err(error) => tracing::warn!(error = %error, "Failed to check the granted scopes"),
+        },
+    }
 
     // Save the token response to a JSON file
-    save_tokens_to_file(&[token_response])?; // Save a list with a single token response
+    save_tokens_to_file(&config.auth.tokens, &[token_response])?; // Save a list with a single token response
 
-    let auth_secret_clone = auth_secret.clone();
+    let theme = theme.get_ref().clone();
+    let config = config.get_ref().clone();
     spawn(async move {
-        fetch_classroom_data(auth_secret_clone).await.expect("Error fetching classroom data");
+        if let Err(error) = fetch_classroom_data(theme, config, settings).await {
+            tracing::error!(error = %error, "Failed to archive the courses");
+        }
     });
 
     // Return the response without waiting for fetch_classroom_data
@@ -397,9 +594,15 @@ impl From<std::io::Error> for MyError {
     }
 }
 
-fn save_tokens_to_file(token_responses: &[StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>]) -> io::Result<()> {
+impl From<archive::Error> for MyError {
+    fn from(error: archive::Error) -> Self {
+        MyError(error.to_string())
+    }
+}
+
+fn save_tokens_to_file(path: &Path, token_responses: &[StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>]) -> io::Result<()> {
     // Read the existing tokens from the file, if it exists
-    let mut existing_tokens: Vec<MyTokenResponse> = match File::open("tokens.json") {
+    let mut existing_tokens: Vec<MyTokenResponse> = match File::open(path) {
         Ok(mut file) => {
             let mut contents = String::new();
             file.read_to_string(&mut contents)?;
@@ -419,7 +622,7 @@ fn save_tokens_to_file(token_responses: &[StandardTokenResponse<EmptyExtraTokenF
         .expect("Failed to serialize token responses");
 
     // Write the updated tokens back to the file
-    let mut file = File::create("tokens.json")?;
+    let mut file = File::create(path)?;
     file.write_all(json.as_bytes())?;
 
     Ok(())
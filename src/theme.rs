@@ -0,0 +1,215 @@
+use crate::rich_text::RichText;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tera::{Context, Tera};
+
+/// Where the page templates and the theme come from.
+#[derive(clap::Args, Clone, Debug)]
+pub struct ThemeOptions {
+    /// Theme directory: a `theme.toml`, optional `templates/` overriding the defaults and `static/` assets
+    #[arg(long, default_value = "themes/boscotech")]
+    pub theme: PathBuf,
+    /// Default templates every theme starts from. Files here other than templates are copied to the output too.
+    #[arg(long, default_value = "templates")]
+    pub templates: PathBuf,
+    /// IANA timezone dates are shown in
+    #[arg(long, default_value = "America/Los_Angeles")]
+    pub timezone: String,
+}
+
+impl Default for ThemeOptions {
+    fn default() -> ThemeOptions {
+        ThemeOptions {
+            theme: PathBuf::from("themes/boscotech"),
+            templates: PathBuf::from("templates"),
+            timezone: "America/Los_Angeles".to_string(),
+        }
+    }
+}
+
+impl ThemeOptions {
+    pub fn load(&self) -> io::Result<Theme> {
+        let path = self.theme.join("theme.toml");
+        let text = fs::read_to_string(&path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let vars = toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+        Ok(Theme { options: self.clone(), vars })
+    }
+}
+
+/// The variables a theme sets, available to every template as `theme`.
+///
+/// ```toml
+/// school_name = "Bosco Tech"
+/// logo = "boscotech-clear.png"
+/// background = "#FFFFE0"
+///
+/// [license]
+/// name = "CC BY-NC-SA 4.0"
+/// url = "http://creativecommons.org/licenses/by-nc-sa/4.0/"
+/// icons = ["https://mirrors.creativecommons.org/presskit/icons/cc.svg"]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeVars {
+    pub school_name: String,
+    /// Path of the logo relative to the output directory, usually one of the theme's static files
+    pub logo: Option<String>,
+    /// Any CSS colour
+    pub background: Option<String>,
+    /// The footer has no license notice when this is left out
+    pub license: Option<License>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct License {
+    pub name: String,
+    pub url: String,
+    pub icons: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Theme {
+    options: ThemeOptions,
+    pub vars: ThemeVars,
+}
+
+impl Theme {
+    /// Loads `base.html`, `course.html`, `courses.html` and any other templates by file name, the theme's own
+    /// templates replacing defaults of the same name.
+    pub fn templates(&self) -> tera::Result<Tera> {
+        let mut files = BTreeMap::new();
+        for dir in [self.options.templates.clone(), self.options.theme.join("templates")] {
+            for path in files_in(&dir).map_err(|e| tera::Error::msg(format!("{}: {}", dir.display(), e)))? {
+                if path.extension().is_some_and(|ext| ext == "html") {
+                    files.insert(path.file_name().unwrap().to_string_lossy().into_owned(), path);
+                }
+            }
+        }
+        let mut tera = Tera::default();
+        tera.add_template_files(files.into_iter().map(|(name, path)| (path, Some(name))).collect::<Vec<_>>())?;
+        tera.register_filter("rich_text", RichText);
+        Ok(tera)
+    }
+
+    /// Adds the theme variables and the timezone to a page's context. `root` leads from the page back to the output
+    /// directory, e.g. `"../"` for course pages.
+    pub fn insert(&self, context: &mut Context, root: &str) {
+        context.insert("theme", &self.vars);
+        context.insert("root", root);
+        context.insert("timezone", &self.options.timezone);
+    }
+
+    /// Copies the default templates' assets and then the theme's `static/` directory into `output`.
+    pub fn copy_static(&self, output: &Path) -> io::Result<()> {
+        for path in files_in(&self.options.templates)? {
+            if path.extension().is_some_and(|ext| ext != "html") {
+                fs::copy(&path, output.join(path.file_name().unwrap()))?;
+            }
+        }
+        copy_dir(&self.options.theme.join("static"), output)
+    }
+}
+
+/// Files directly in `dir`, nothing when it doesn't exist.
+fn files_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    if !from.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        let target = to.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            fs::copy(&path, &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn repo(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+    }
+
+    /// The boscotech theme with the default templates, as other modules' tests render their pages.
+    pub(crate) fn bundled() -> Theme {
+        ThemeOptions { theme: repo("themes/boscotech"), templates: repo("templates"), ..Default::default() }.load().unwrap()
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("csee-theme-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn bundled_themes_render_both_pages() {
+        for theme in ["themes/boscotech", "themes/csee"] {
+            let theme = ThemeOptions { theme: repo(theme), templates: repo("templates"), ..Default::default() }.load().unwrap();
+            let tera = theme.templates().unwrap();
+            let mut context = crate::index::index_context(&[], &Default::default());
+            theme.insert(&mut context, "");
+            let html = tera.render("courses.html", &context).unwrap();
+            assert!(html.contains(&format!("List of {} classes", theme.vars.school_name)), "{}", html);
+            assert!(html.contains(r#"href="styles.css""#));
+        }
+    }
+
+    #[test]
+    fn theme_templates_and_assets_override_the_defaults() {
+        let dir = scratch("override");
+        fs::create_dir_all(dir.join("templates")).unwrap();
+        fs::create_dir_all(dir.join("static/fonts")).unwrap();
+        fs::write(dir.join("theme.toml"), "school_name = \"Test School\"\n").unwrap();
+        fs::write(dir.join("templates/courses.html"), "{% extends \"base.html\" %}{% block content %}Overridden{% endblock content %}").unwrap();
+        fs::write(dir.join("static/styles.css"), "body {}").unwrap();
+        fs::write(dir.join("static/fonts/school.woff2"), "font").unwrap();
+        let theme = ThemeOptions { theme: dir.clone(), templates: repo("templates"), ..Default::default() }.load().unwrap();
+
+        let mut context = Context::new();
+        theme.insert(&mut context, "");
+        let html = theme.templates().unwrap().render("courses.html", &context).unwrap();
+        assert!(html.contains("Overridden"));
+        assert!(!html.contains("This work is licensed"), "no license was configured");
+
+        let output = dir.join("output");
+        fs::create_dir_all(&output).unwrap();
+        theme.copy_static(&output).unwrap();
+        assert_eq!(fs::read_to_string(output.join("styles.css")).unwrap(), "body {}");
+        assert!(output.join("fonts/school.woff2").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_theme_keys_are_an_error() {
+        let dir = scratch("unknown");
+        fs::write(dir.join("theme.toml"), "school = \"Typo\"\n").unwrap();
+        let error = ThemeOptions { theme: dir.clone(), templates: repo("templates"), ..Default::default() }.load().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}